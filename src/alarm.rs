@@ -0,0 +1,168 @@
+use crate::config::Config;
+
+use chrono::{DateTime, Utc};
+use icalendar::{Alarm, Component, Trigger};
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// Reminder some time before event start, e.g. `15m`, `2h` or `1d`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Reminder {
+    pub minutes: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AlarmLimits {
+    /// Maximum number of reminders per event
+    pub max_count: usize,
+    /// Maximum reminder advance for lessons in minutes
+    pub max_lesson_minutes: u32,
+    /// Maximum reminder advance for exams in minutes
+    pub max_exam_minutes: u32,
+}
+
+impl Default for AlarmLimits {
+    fn default() -> Self {
+        Self {
+            max_count: 3,
+            max_lesson_minutes: 24 * 60,
+            max_exam_minutes: 7 * 24 * 60,
+        }
+    }
+}
+
+impl FromStr for Reminder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(|| format!("Missing unit in reminder {s:?}, use m, h, d or w"))?;
+        let (value, unit) = s.split_at(split);
+        let value: u32 = value
+            .parse()
+            .map_err(|_| format!("Incorrect reminder {s:?}"))?;
+        let multiplier = match unit {
+            "m" => 1,
+            "h" => 60,
+            "d" => 24 * 60,
+            "w" => 7 * 24 * 60,
+            _ => return Err(format!("Unknown unit in reminder {s:?}, use m, h, d or w")),
+        };
+        let minutes = value
+            .checked_mul(multiplier)
+            .ok_or_else(|| format!("Reminder {s:?} is too long"))?;
+
+        Ok(Self { minutes })
+    }
+}
+
+impl TryFrom<String> for Reminder {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Reminder> for String {
+    fn from(value: Reminder) -> Self {
+        value.to_string()
+    }
+}
+
+impl fmt::Display for Reminder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.minutes {
+            m if m != 0 && m % (24 * 60) == 0 => write!(f, "{}d", m / (24 * 60)),
+            m if m != 0 && m % 60 == 0 => write!(f, "{}h", m / 60),
+            m => write!(f, "{m}m"),
+        }
+    }
+}
+
+impl Reminder {
+    /// Alarm of the event with `event_uid`, stamped like the event itself.
+    pub fn to_alarm(self, description: &str, event_uid: &str, stamp: DateTime<Utc>) -> Alarm {
+        let trigger = Trigger::before_start(chrono::Duration::minutes(self.minutes.into()));
+        let mut alarm = Alarm::display(description, trigger);
+        alarm
+            .uid(&format!("{event_uid}-alarm-{self}"))
+            .timestamp(stamp);
+        alarm
+    }
+}
+
+/// Parses comma separated list of reminders, e.g. `1d,2h`.
+pub fn parse_reminders(s: &str) -> Result<Vec<Reminder>, String> {
+    s.split(',')
+        .filter(|r| !r.trim().is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// Checks reminders against `AlarmLimits`.
+pub fn validate_reminders(
+    cfg: &Config,
+    lesson: &[Reminder],
+    exam: &[Reminder],
+) -> Result<(), String> {
+    let limits = &cfg.alarms;
+    for (reminders, max_minutes, kind) in [
+        (lesson, limits.max_lesson_minutes, "lesson"),
+        (exam, limits.max_exam_minutes, "exam"),
+    ] {
+        if reminders.len() > limits.max_count {
+            return Err(format!(
+                "Too many {kind} reminders, at most {} allowed",
+                limits.max_count
+            ));
+        }
+        if let Some(r) = reminders.iter().find(|r| r.minutes > max_minutes) {
+            return Err(format!(
+                "{kind} reminder {r} is too early, at most {} allowed",
+                Reminder {
+                    minutes: max_minutes
+                }
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_format() {
+        assert_eq!(
+            parse_reminders("15m, 2h,1d").unwrap(),
+            vec![
+                Reminder { minutes: 15 },
+                Reminder { minutes: 120 },
+                Reminder { minutes: 1440 }
+            ]
+        );
+        assert_eq!(Reminder { minutes: 90 }.to_string(), "90m");
+        assert_eq!(Reminder { minutes: 120 }.to_string(), "2h");
+        assert!("15".parse::<Reminder>().is_err());
+        assert!("m".parse::<Reminder>().is_err());
+        assert!("15s".parse::<Reminder>().is_err());
+    }
+
+    #[test]
+    fn limits() {
+        let cfg = Config::default();
+        let r = |s: &str| parse_reminders(s).unwrap();
+
+        assert!(validate_reminders(&cfg, &r("15m"), &r("1d,2h")).is_ok());
+        assert!(validate_reminders(&cfg, &r("2d"), &[]).is_err());
+        assert!(validate_reminders(&cfg, &[], &r("2w")).is_err());
+        assert!(validate_reminders(&cfg, &r("1m,2m,3m,4m"), &[]).is_err());
+    }
+}
@@ -0,0 +1,249 @@
+use crate::{
+    db,
+    models::{Lesson, Schedule},
+    snapshot,
+    tracto::{RequestError, RequestResult},
+    Request,
+};
+
+use chrono::NaiveTime;
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt};
+
+/// Pair times for schedules telling none of them.
+const DEFAULT_GRID: [(u8, &str, &str); 8] = [
+    (1, "08:20", "09:50"),
+    (2, "10:00", "11:35"),
+    (3, "12:05", "13:40"),
+    (4, "13:50", "15:25"),
+    (5, "15:35", "17:10"),
+    (6, "17:20", "18:40"),
+    (7, "18:45", "20:05"),
+    (8, "20:10", "21:30"),
+];
+
+/// Start and end of every pair by `lesson_number`.
+pub type Grid = BTreeMap<u8, (NaiveTime, NaiveTime)>;
+
+fn span(lesson: &Lesson) -> Option<(NaiveTime, NaiveTime)> {
+    let time = &lesson.lesson_time;
+    let start = NaiveTime::from_hms_opt(time.hour_start, time.minute_start, 0)?;
+    let end = NaiveTime::from_hms_opt(time.hour_end, time.minute_end, 0)?;
+    Some((start, end))
+}
+
+/// The most common times of every pair.
+pub fn grid<'a>(lessons: impl Iterator<Item = &'a Lesson>) -> Grid {
+    let mut counts: BTreeMap<u8, BTreeMap<(NaiveTime, NaiveTime), usize>> = BTreeMap::new();
+    for lesson in lessons {
+        if let (Some(span), 1..) = (span(lesson), lesson.lesson_time.lesson_number) {
+            *counts
+                .entry(lesson.lesson_time.lesson_number)
+                .or_default()
+                .entry(span)
+                .or_default() += 1;
+        }
+    }
+    if counts.is_empty() {
+        return DEFAULT_GRID
+            .iter()
+            .map(|&(number, start, end)| {
+                let time = |s| NaiveTime::parse_from_str(s, "%H:%M").unwrap();
+                (number, (time(start), time(end)))
+            })
+            .collect();
+    }
+
+    counts
+        .into_iter()
+        .map(|(number, times)| {
+            let (span, _) = times
+                .into_iter()
+                .max_by(|(a, n), (b, m)| n.cmp(m).then(b.cmp(a)))
+                .unwrap();
+            (number, span)
+        })
+        .collect()
+}
+
+fn hm(time: NaiveTime) -> String {
+    time.format("%H:%M").to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Bell {
+    pub lesson_number: u8,
+    pub start: String,
+    pub end: String,
+}
+
+/// Lesson taking place at other times than its pair.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Mismatch {
+    /// Group as `form/group`
+    pub group: String,
+    pub lesson_id: u32,
+    pub name: String,
+    pub day_number: u32,
+    pub lesson_number: u8,
+    pub time: String,
+    pub expected: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} on day {}, pair {}: {} instead of {}",
+            self.name, self.group, self.day_number, self.lesson_number, self.time, self.expected
+        )
+    }
+}
+
+/// Lessons of the schedule not matching the grid. Pairs missing
+/// from the grid are not checked.
+pub fn mismatches(grid: &Grid, group: &str, schedule: &Schedule) -> Vec<Mismatch> {
+    schedule
+        .lessons
+        .iter()
+        .filter_map(|lesson| {
+            let number = lesson.lesson_time.lesson_number;
+            let &(start, end) = grid.get(&number)?;
+            let actual = span(lesson);
+            (actual != Some((start, end))).then(|| Mismatch {
+                group: group.to_string(),
+                lesson_id: lesson.id,
+                name: lesson.name.clone(),
+                day_number: lesson.day.day_number,
+                lesson_number: number,
+                time: actual
+                    .map(|(start, end)| format!("{}-{}", hm(start), hm(end)))
+                    .unwrap_or_else(|| "incorrect time".to_string()),
+                expected: format!("{}-{}", hm(start), hm(end)),
+            })
+        })
+        .collect()
+}
+
+/// Bell schedule of a department and lessons breaking it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BellsReport {
+    pub department: String,
+    /// Number of group schedules the grid comes from
+    pub groups: usize,
+    pub bells: Vec<Bell>,
+    pub mismatches: Vec<Mismatch>,
+}
+
+/// Report of schedules given as (`form/group`, schedule).
+pub fn report(department: &str, schedules: &[(String, Schedule)]) -> BellsReport {
+    let grid = grid(schedules.iter().flat_map(|(_, schedule)| &schedule.lessons));
+    BellsReport {
+        department: department.to_string(),
+        groups: schedules.len(),
+        bells: grid
+            .iter()
+            .map(|(&lesson_number, &(start, end))| Bell {
+                lesson_number,
+                start: hm(start),
+                end: hm(end),
+            })
+            .collect(),
+        mismatches: schedules
+            .iter()
+            .flat_map(|(group, schedule)| mismatches(&grid, group, schedule))
+            .collect(),
+    }
+}
+
+/// Report of the department, made from the latest snapshots of its groups.
+pub fn department_report(department: &str) -> RequestResult<BellsReport> {
+    let conn = db::open().map_err(|e| RequestError::Io(e.to_string()))?;
+    let groups =
+        db::department_groups(&conn, department).map_err(|e| RequestError::Io(e.to_string()))?;
+    if groups.is_empty() {
+        return Err(RequestError::Invalid(format!(
+            "No schedules of department {department} are known, crawl it first"
+        )));
+    }
+
+    let mut schedules = Vec::new();
+    for (form, group) in groups {
+        let request = Request {
+            department: department.to_string(),
+            form,
+            group,
+            ..Default::default()
+        };
+        if let Some(snapshot) = snapshot::load_latest::<Schedule>(&request)? {
+            schedules.push((format!("{}/{}", request.form, request.group), snapshot.data));
+        }
+    }
+    Ok(report(department, &schedules))
+}
+
+/// Logs every mismatch of the report.
+pub fn warn_mismatches(report: &BellsReport) {
+    for mismatch in &report.mismatches {
+        log::warn!("Off the bell schedule of {}: {mismatch}", report.department);
+    }
+}
+
+/// Report as text for the terminal.
+pub fn table(report: &BellsReport) -> String {
+    let mut out = format!(
+        "Bell schedule of {} from {} group(s):\n",
+        report.department, report.groups
+    );
+    for bell in &report.bells {
+        out += &format!("{:>2}  {}-{}\n", bell.lesson_number, bell.start, bell.end);
+    }
+    if !report.mismatches.is_empty() {
+        out += &format!("{} lesson(s) off the schedule:\n", report.mismatches.len());
+        for mismatch in &report.mismatches {
+            out += &format!("  {mismatch}\n");
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::*;
+
+    #[test]
+    fn grid_is_the_most_common_time() {
+        let mut late = lesson_json(3, 2, "FULL", "");
+        late["lessonTime"]["minuteEnd"] = 30.into();
+        late["name"] = "Поздняя пара".into();
+        let schedules = vec![
+            (
+                "full/351".to_string(),
+                schedule(vec![lesson_json(1, 1, "FULL", "")]),
+            ),
+            (
+                "full/352".to_string(),
+                schedule(vec![lesson_json(2, 3, "NOM", ""), late]),
+            ),
+        ];
+
+        let report = report("knt", &schedules);
+        assert_eq!(report.groups, 2);
+        assert_eq!(
+            report.bells,
+            [Bell {
+                lesson_number: 1,
+                start: "08:20".to_string(),
+                end: "09:50".to_string(),
+            }]
+        );
+        assert_eq!(report.mismatches.len(), 1);
+        assert_eq!(
+            report.mismatches[0].to_string(),
+            "Поздняя пара of full/352 on day 2, pair 1: 08:20-09:30 instead of 08:20-09:50"
+        );
+
+        assert_eq!(grid(std::iter::empty()).len(), DEFAULT_GRID.len());
+    }
+}
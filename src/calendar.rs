@@ -1,30 +1,143 @@
-use crate::{config::*, models::*, Request};
+use crate::{config::*, expand::Term, filter, models::*, template::RenderedEvent, Request};
 
 use chrono::prelude::*;
-use chrono_tz::Europe::Saratov;
+use chrono_tz::{Europe::Saratov, Tz};
 use icalendar::*;
+use serde::{Deserialize, Serialize};
 
-impl Schedule {
+/// How lesson type is shown in event summary.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SummaryStyle {
+    /// "Name (Л)"
+    #[default]
+    Short,
+    /// "Name (Лекция)"
+    Full,
+    /// Just "Name"
+    None,
+}
+
+/// Lessons and exams of a single group merged into one feed.
+pub struct Combined {
+    pub schedule: Schedule,
+    pub exams: ExamList,
+}
+
+impl Combined {
     pub fn to_ical(&self, cfg: &Config, request: &Request) -> Calendar {
         let mut cal = Calendar::new();
-        for lesson in &self.lessons {
-            let same_subgroup = request
-                .subgroups
-                .contains(&lesson.sub_group.trim().to_string());
-            if (request.subgroups.is_empty() || lesson.sub_group.is_empty() || same_subgroup)
-                && (!lesson.name.contains(&cfg.translator_substr) || request.translator)
-            {
-                cal.push(lesson.to_event(cfg));
+        self.schedule
+            .push_events(&mut cal, cfg, request, &self.term(cfg, request));
+        for exam in &self.exams.exam_period_events {
+            if let Some(event) = exam.to_event(cfg, request) {
+                cal.push(event);
+            }
+        }
+        cal.done()
+    }
+
+    /// Days of weekly lessons, see `Request::stop_at_session`.
+    pub fn term(&self, cfg: &Config, request: &Request) -> Term {
+        Term::new(cfg).until(self.last_day(cfg, request))
+    }
+
+    /// Last day of weekly lessons, see `Request::stop_at_session`.
+    fn last_day(&self, cfg: &Config, request: &Request) -> NaiveDate {
+        let last_day = semester_end(cfg);
+        match self.exams.session_start(cfg) {
+            Some(session_start) if request.stop_at_session => {
+                last_day.min(session_start.pred_opt().unwrap())
             }
+            _ => last_day,
         }
+    }
+}
+
+fn current_year() -> i32 {
+    Utc::now().with_timezone(&Saratov).date_naive().year()
+}
 
+pub fn semester_start(cfg: &Config) -> NaiveDate {
+    NaiveDate::from_ymd_opt(
+        current_year(),
+        cfg.semester.start_md.0,
+        cfg.semester.start_md.1,
+    )
+    .unwrap()
+}
+
+/// Type of the week `date` belongs to, every odd week is NOM
+/// and every even week is DENOM.
+pub fn week_type(date: NaiveDate) -> WeekType {
+    if date.iso_week().week().is_multiple_of(2) {
+        WeekType::Denom
+    } else {
+        WeekType::Nom
+    }
+}
+
+pub fn semester_end(cfg: &Config) -> NaiveDate {
+    NaiveDate::from_ymd_opt(current_year(), cfg.semester.end_md.0, cfg.semester.end_md.1).unwrap()
+}
+
+impl Schedule {
+    pub fn to_ical(&self, cfg: &Config, request: &Request) -> Calendar {
+        let mut cal = Calendar::new();
+        self.push_events(&mut cal, cfg, request, &Term::new(cfg));
         cal.done()
     }
+
+    pub fn requested_lessons<'a>(
+        &'a self,
+        cfg: &'a Config,
+        request: &'a Request,
+    ) -> impl Iterator<Item = &'a Lesson> {
+        self.lessons
+            .iter()
+            .filter(|lesson| lesson.is_requested(cfg, request))
+    }
+
+    fn push_events(&self, cal: &mut Calendar, cfg: &Config, request: &Request, term: &Term) {
+        for lesson in self.requested_lessons(cfg, request) {
+            cal.push(lesson.to_event(cfg, request, term));
+        }
+    }
 }
 
 impl Lesson {
-    fn to_event(&self, cfg: &Config) -> Event {
-        let cur_year = Utc::now().with_timezone(&Saratov).date_naive().year();
+    /// Whether the lesson passes subgroup, translator and lesson filters of the request.
+    /// Lessons of a merged schedule are checked against the subgroups of their own group.
+    pub fn is_requested(&self, cfg: &Config, request: &Request) -> bool {
+        let subgroups = self.requested_subgroups(request);
+        let same_subgroup = subgroups.contains(&self.sub_group.trim().to_string());
+        (subgroups.is_empty() || self.sub_group.is_empty() || same_subgroup)
+            && (!self.name.contains(&cfg.translator_substr) || request.translator)
+            && filter::passes(&request.filters, cfg, self)
+    }
+
+    fn requested_subgroups<'a>(&self, request: &'a Request) -> &'a [String] {
+        let group = &self.student_group;
+        let is = |department: &str, form: &str, number: &str| {
+            group.department.url == department
+                && group.education_form.as_str().eq_ignore_ascii_case(form)
+                && group.group_number == number
+        };
+        request
+            .with
+            .iter()
+            .find(|other| is(&other.department, &other.form, &other.group))
+            .map_or(&request.subgroups, |other| &other.subgroups)
+    }
+
+    /// Stable event UID, so clients update the event instead of duplicating it.
+    pub fn uid(&self) -> String {
+        format!("lesson-{}@calar", self.id)
+    }
+
+    /// Start and end of the first occurrence in the semester.
+    pub fn first_span(&self, cfg: &Config) -> (DateTime<Tz>, DateTime<Tz>) {
+        let cur_year = current_year();
         let mut event_start = Saratov
             .with_ymd_and_hms(
                 cur_year,
@@ -46,87 +159,361 @@ impl Lesson {
             )
             .unwrap();
 
-        // Interval is steps in weeks for every recurring event.
-        // If week_type is FULL, lesson occurs each week
-        // otherwise every other week
-        let interval = match self.week_type.as_str() {
-            "FULL" => 1,
-            _ => 2,
-        };
-        let rrule_end =
-            NaiveDate::from_ymd_opt(cur_year, cfg.semester.end_md.0, cfg.semester.end_md.1)
-                .unwrap()
-                .format("%Y%m%dT235959")
-                .to_string();
-        let rrule = format!("FREQ=WEEKLY;INTERVAL={interval};UNTIL={rrule_end}");
-
-        // This logic below uses the fact that every odd week is NOM
-        // and every even week should be DENOM
-        let first_week_of_sem = chrono::NaiveDate::from_ymd_opt(
-            cur_year,
-            cfg.semester.start_md.0,
-            cfg.semester.start_md.1,
-        )
-        .unwrap()
-        .iso_week()
-        .week();
-        if first_week_of_sem % 2 == 0 && self.week_type == "NOM"
-            || first_week_of_sem % 2 == 1 && self.week_type == "DENOM"
-        {
+        let alternating = matches!(self.week_type, WeekType::Nom | WeekType::Denom);
+        if alternating && self.week_type != week_type(semester_start(cfg)) {
             event_start += chrono::Duration::weeks(1);
             event_end += chrono::Duration::weeks(1);
         }
 
-        Event::new()
+        (event_start, event_end)
+    }
+
+    /// Weeks between occurrences.
+    ///
+    /// If week_type is FULL, lesson occurs each week
+    /// otherwise every other week. Unknown week types are shown
+    /// every week, so no lesson gets lost.
+    pub fn interval(&self) -> u32 {
+        match self.week_type {
+            WeekType::Full | WeekType::Unknown(_) => 1,
+            WeekType::Nom | WeekType::Denom => 2,
+        }
+    }
+
+    pub fn text(&self, cfg: &Config, request: &Request) -> RenderedEvent {
+        cfg.templates
+            .lesson(request.template.as_deref())
+            .render(&self.template_vars(cfg, request.style))
+    }
+
+    fn to_event(&self, cfg: &Config, request: &Request, term: &Term) -> Event {
+        let (event_start, event_end) = self.first_span(cfg);
+        let rrule_end = term.end.format("%Y%m%dT235959").to_string();
+        let rrule = format!("FREQ=WEEKLY;INTERVAL={};UNTIL={rrule_end}", self.interval());
+        let text = self.text(cfg, request);
+
+        let uid = self.uid();
+        let stamp = Utc
+            .timestamp_opt(self.updated_timestamp.into(), 0)
+            .single()
+            .unwrap_or_default();
+
+        let mut event = Event::new();
+        event
+            .uid(&uid)
+            .timestamp(stamp)
             .starts(CalendarDateTime::from_date_time(event_start))
             .ends(CalendarDateTime::from_date_time(event_end))
-            .summary(self.summary().as_str())
-            .description(self.teacher.full().as_str())
-            .location(self.place.as_str())
-            .append_property(Property::new("RRULE", rrule.as_str()).done())
-            .done()
+            .summary(text.summary.as_str())
+            .description(text.description.as_str())
+            .location(text.location.as_str())
+            .add_property(
+                "CATEGORIES",
+                cfg.lesson_type_label(&self.lesson_type).full.as_str(),
+            )
+            .append_property(Property::new("RRULE", rrule.as_str()).done());
+        let excluded = self.expand(cfg, term).excluded;
+        if !excluded.is_empty() {
+            let exdates: Vec<String> = excluded
+                .iter()
+                .map(|start| start.format("%Y%m%dT%H%M%S").to_string())
+                .collect();
+            event.append_property(
+                Property::new("EXDATE", exdates.join(",").as_str())
+                    .add_parameter("TZID", Saratov.name())
+                    .done(),
+            );
+        }
+        for reminder in &request.alarm {
+            event.alarm(reminder.to_alarm(&text.summary, &uid, stamp));
+        }
+        event.done()
     }
 }
 
 impl ExamList {
-    pub fn to_ical(&self) -> Calendar {
+    pub fn to_ical(&self, cfg: &Config, request: &Request) -> Calendar {
         let mut calendar = Calendar::new();
         for exam in &self.exam_period_events {
-            calendar.push(exam.to_event());
+            if let Some(event) = exam.to_event(cfg, request) {
+                calendar.push(event);
+            }
         }
         calendar.done()
     }
+
+    /// Date of the earliest exam event of the current semester.
+    ///
+    /// Events dated before the semester start belong to the previous
+    /// session and are ignored.
+    pub fn session_start(&self, cfg: &Config) -> Option<NaiveDate> {
+        let sem_start = semester_start(cfg);
+        self.exam_period_events
+            .iter()
+            .filter_map(|exam| exam.date())
+            .filter(|date| *date >= sem_start)
+            .min()
+    }
 }
 
 impl ExamEvent {
-    fn to_event(&self) -> Event {
-        let cur_year = self.year.replace("г.", "").parse::<i32>().unwrap();
-        let event_start = Saratov
-            .with_ymd_and_hms(
-                cur_year,
-                self.month.number,
+    /// Date of the event, `None` for a malformed Tracto record.
+    fn date(&self) -> Option<NaiveDate> {
+        let date = self
+            .year
+            .replace("г.", "")
+            .trim()
+            .parse::<i32>()
+            .ok()
+            .and_then(|year| NaiveDate::from_ymd_opt(year, self.month.number, self.day));
+        if date.is_none() {
+            log::warn!(
+                "Skipping exam {} with incorrect date {} {} {}",
+                self.id,
                 self.day,
-                self.hour,
-                self.minute,
-                0,
-            )
-            .unwrap();
-        let event_end = Saratov
-            .with_ymd_and_hms(
-                cur_year,
                 self.month.number,
-                self.day,
-                23,
-                59,
-                0,
-            )
-            .unwrap();
-        Event::new()
+                self.year
+            );
+        }
+        date
+    }
+
+    pub fn uid(&self) -> String {
+        format!("exam-{}@calar", self.id)
+    }
+
+    /// Start and end of the event, `None` for a malformed Tracto record.
+    pub fn span(&self, cfg: &Config) -> Option<(DateTime<Tz>, DateTime<Tz>)> {
+        let start = self.date()?.and_hms_opt(self.hour, self.minute, 0);
+        let Some(event_start) =
+            start.and_then(|start| Saratov.from_local_datetime(&start).single())
+        else {
+            log::warn!(
+                "Skipping exam {} with incorrect time {}:{}",
+                self.id,
+                self.hour,
+                self.minute
+            );
+            return None;
+        };
+        let duration = cfg.exams.duration(
+            &self.student_group.department.url,
+            &self.exam_period_event_type,
+        );
+        Some((
+            event_start,
+            event_start + chrono::Duration::minutes(duration.into()),
+        ))
+    }
+
+    pub fn text(&self, cfg: &Config, request: &Request) -> RenderedEvent {
+        cfg.templates
+            .exam(request.template.as_deref())
+            .render(&self.template_vars(cfg))
+    }
+
+    fn to_event(&self, cfg: &Config, request: &Request) -> Option<Event> {
+        let (event_start, event_end) = self.span(cfg)?;
+        let text = self.text(cfg, request);
+
+        // Tracto has no modification time of exams, the start is stable
+        // enough to keep exported calendars reproducible.
+        let uid = self.uid();
+        let stamp = event_start.with_timezone(&Utc);
+
+        let mut event = Event::new();
+        event
+            .uid(&uid)
+            .timestamp(stamp)
             .starts(CalendarDateTime::from_date_time(event_start))
             .ends(CalendarDateTime::from_date_time(event_end))
-            .summary(self.summary().as_str())
-            .description(self.teacher.full().as_str())
-            .location(self.place.as_str())
-            .done()
+            .summary(text.summary.as_str())
+            .description(text.description.as_str())
+            .location(text.location.as_str());
+        for reminder in &request.exam_alarm {
+            event.alarm(reminder.to_alarm(&text.summary, &uid, stamp));
+        }
+        if let Some(style) = cfg.exams.styles.get(&self.exam_period_event_type) {
+            event
+                .add_property("CATEGORIES", style.category.as_str())
+                .add_property("COLOR", style.color.as_str());
+        }
+        Some(event.done())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::*;
+
+    #[test]
+    fn session_start_ignores_previous_session() {
+        let cfg = Config::default();
+        let year = current_year();
+        let exams = exam_list(vec![
+            exam_json(1, year, 1, 15),
+            exam_json(2, year, 6, 20),
+            exam_json(3, year, 6, 10),
+        ]);
+        assert_eq!(
+            exams.session_start(&cfg),
+            NaiveDate::from_ymd_opt(year, 6, 10)
+        );
+    }
+
+    #[test]
+    fn malformed_exams_are_skipped() {
+        let cfg = Config::default();
+        let year = current_year();
+        let mut broken = exam_json(1, year, 6, 5);
+        broken["year"] = "г.".into();
+        let exams = exam_list(vec![broken, exam_json(2, year, 6, 10)]);
+
+        assert_eq!(
+            exams.session_start(&cfg),
+            NaiveDate::from_ymd_opt(year, 6, 10)
+        );
+        let ics = exams.to_ical(&cfg, &Request::default()).to_string();
+        assert!(!ics.contains("exam-1@calar"));
+        assert!(ics.contains("exam-2@calar"));
+        assert_eq!(exams.occurrences(&cfg, &Request::default()).len(), 1);
+    }
+
+    #[test]
+    fn combined_stops_lessons_at_session() {
+        let cfg = Config::default();
+        let year = current_year();
+        let combined = Combined {
+            schedule: schedule(vec![lesson_json(1, 1, "FULL", "")]),
+            exams: exam_list(vec![exam_json(1, year, 5, 20)]),
+        };
+        let request = Request {
+            exams: true,
+            stop_at_session: true,
+            ..Default::default()
+        };
+
+        let ics = combined.to_ical(&cfg, &request).to_string();
+        assert!(ics.contains(&format!("UNTIL={year}0519T235959")));
+        assert!(ics.contains("Экзамен 1"));
+
+        let request = Request {
+            exams: true,
+            ..Default::default()
+        };
+        let ics = combined.to_ical(&cfg, &request).to_string();
+        assert!(ics.contains(&format!("UNTIL={year}0531T235959")));
+    }
+
+    #[test]
+    fn exam_duration_by_type_and_department() {
+        let mut cfg = Config::default();
+        cfg.exams
+            .department_durations
+            .insert("knt".to_string(), [(ExamType::Exam, 180)].into());
+
+        let exams = exam_list(vec![
+            typed_exam_json(1, "CONSULTATION", 2023, 6, 10),
+            typed_exam_json(2, "EXAM", 2023, 6, 12),
+        ]);
+        let ics = exams.to_ical(&cfg, &Request::default()).to_string();
+
+        assert!(ics.contains("DTEND;TZID=Europe/Saratov:20230610T113000"));
+        assert!(ics.contains("DTEND;TZID=Europe/Saratov:20230612T130000"));
+        assert!(ics.contains("CATEGORIES:Консультация"));
+        assert!(ics.contains("COLOR:crimson"));
+    }
+
+    #[test]
+    fn lesson_type_labels_and_summary_style() {
+        let cfg = Config::default();
+        let mut schedule = schedule(vec![lesson_json(1, 1, "FULL", "")]);
+        schedule.lessons[0].lesson_type = LessonType::Laboratory;
+
+        let ics = schedule.to_ical(&cfg, &Request::default()).to_string();
+        assert!(ics.contains("SUMMARY:Предмет 1 (Лаб)"));
+        assert!(ics.contains("CATEGORIES:Лабораторная работа"));
+
+        let request = Request {
+            style: SummaryStyle::Full,
+            ..Default::default()
+        };
+        let ics = schedule.to_ical(&cfg, &request).to_string();
+        assert!(ics.contains("SUMMARY:Предмет 1 (Лабораторная работа)"));
+
+        let request = Request {
+            style: SummaryStyle::None,
+            ..Default::default()
+        };
+        let ics = schedule.to_ical(&cfg, &request).to_string();
+        assert!(ics.contains("SUMMARY:Предмет 1\r\n"));
+    }
+
+    #[test]
+    fn lesson_template_preset() {
+        let mut cfg = Config::default();
+        cfg.places
+            .insert("12 корпус".to_string(), "ул. Астраханская, 83".to_string());
+        let schedule = schedule(vec![lesson_json(1, 1, "FULL", "1_под.")]);
+        let request = Request {
+            template: Some("detailed".to_string()),
+            ..Default::default()
+        };
+
+        let ics = schedule.to_ical(&cfg, &request).to_string();
+        assert!(ics.contains("SUMMARY:Предмет 1 (Л)"));
+        assert!(ics.contains("DESCRIPTION:Лекция, 1 пара\\nИванов"));
+        assert!(ics.contains("LOCATION:ул. Астраханская, 83"));
+
+        let request = Request {
+            template: Some("initials".to_string()),
+            ..Default::default()
+        };
+        let ics = schedule.to_ical(&cfg, &request).to_string();
+        assert!(ics.contains("SUMMARY:Предмет 1 (Л) Иванов И. И."));
+    }
+
+    #[test]
+    fn reminders_are_rendered_as_alarms() {
+        let cfg = Config::default();
+        let year = current_year();
+        let combined = Combined {
+            schedule: schedule(vec![lesson_json(1, 1, "FULL", "")]),
+            exams: exam_list(vec![exam_json(1, year, 6, 20)]),
+        };
+        let request = Request {
+            exams: true,
+            alarm: vec!["15m".parse().unwrap()],
+            exam_alarm: vec!["1d".parse().unwrap(), "2h".parse().unwrap()],
+            ..Default::default()
+        };
+
+        let ics = combined.to_ical(&cfg, &request).to_string();
+        assert_eq!(ics.matches("BEGIN:VALARM").count(), 3);
+        assert!(ics.contains("TRIGGER;RELATED=START:-PT900S"));
+        assert!(ics.contains("TRIGGER;RELATED=START:-P1D"));
+    }
+
+    #[test]
+    fn rendering_is_deterministic() {
+        let cfg = Config::default();
+        let combined = Combined {
+            schedule: schedule(vec![lesson_json(1, 1, "FULL", "")]),
+            exams: exam_list(vec![exam_json(1, 2023, 6, 20)]),
+        };
+        let request = Request {
+            exams: true,
+            alarm: vec!["15m".parse().unwrap()],
+            ..Default::default()
+        };
+
+        let ics = combined.to_ical(&cfg, &request).to_string();
+        assert_eq!(ics, combined.to_ical(&cfg, &request).to_string());
+        assert!(ics.contains("UID:lesson-1@calar"));
+        assert!(ics.contains("UID:lesson-1@calar-alarm-15m"));
+        assert!(ics.contains("UID:exam-1@calar"));
+        assert!(ics.contains("DTSTAMP:19700101T000000Z"));
+        assert!(ics.contains("DTSTAMP:20230620T060000Z"));
     }
 }
@@ -1,10 +1,35 @@
+use crate::{
+    alarm::AlarmLimits,
+    crawl::CrawlConfig,
+    models::{ExamType, LessonType},
+    subgroup::SubgroupsConfig,
+    template::Templates,
+    webhook::WebhooksConfig,
+};
+
 use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::PathBuf};
 
 pub const QUALIFIER: &str = "dev";
 pub const APP_NAME: &str = "Calar";
 pub const ORG_NAME: &str = "calar";
 
+/// Environment variable overriding the config file location.
+pub const CONFIG_ENV: &str = "CALAR_CONFIG";
+/// Environment variable overriding the data directory.
+pub const DATA_DIR_ENV: &str = "CALAR_DATA_DIR";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Cannot read {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+
+    #[error("Cannot parse {0}: {1}")]
+    Parse(PathBuf, serde_json::Error),
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub app_name: String,
     pub addr: String,
@@ -12,12 +37,123 @@ pub struct Config {
     pub tracto_prefix: String,
     pub translator_substr: String,
     pub semester: Semester,
+    pub exams: Exams,
+    /// Labels used in lesson summaries and categories
+    pub lesson_types: HashMap<LessonType, LessonTypeLabel>,
+    /// Event text templates, see `template.rs`
+    pub templates: Templates,
+    /// Addresses by building, matched as a prefix of lesson place
+    pub places: HashMap<String, String>,
+    pub alarms: AlarmLimits,
+    pub webhooks: WebhooksConfig,
+    pub crawl: CrawlConfig,
+    pub subgroups: SubgroupsConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Semester {
     pub end_md: (u32, u32),
     pub start_md: (u32, u32),
+    /// Days off, excluded from weekly lessons. There are none
+    /// unless listed, since days off are moved every year.
+    #[serde(default)]
+    pub holidays: Vec<Holiday>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Holiday {
+    pub name: String,
+    pub start_md: (u32, u32),
+    /// Last day off, the same as the first one unless set
+    #[serde(default)]
+    pub end_md: Option<(u32, u32)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LessonTypeLabel {
+    pub short: String,
+    pub full: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Exams {
+    /// Event duration in minutes by `exam_period_event_type`
+    pub durations: HashMap<ExamType, u32>,
+    /// Per-department overrides of `durations`, keyed by department url
+    pub department_durations: HashMap<String, HashMap<ExamType, u32>>,
+    /// Duration in minutes for types missing from `durations`
+    pub default_duration: u32,
+    /// Category and color by `exam_period_event_type`
+    pub styles: HashMap<ExamType, ExamStyle>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExamStyle {
+    pub category: String,
+    /// CSS3 color name, see RFC 7986
+    pub color: String,
+}
+
+impl Config {
+    /// Reads config from `$CALAR_CONFIG` or `config.json` in the user config
+    /// directory. Missing file or missing fields fall back to defaults.
+    pub fn load() -> Result<Self, ConfigError> {
+        let path = match std::env::var_os(CONFIG_ENV) {
+            Some(path) => PathBuf::from(path),
+            None => match get_config_dir() {
+                Some(dir) => dir.join("config.json"),
+                None => return Ok(Self::default()),
+            },
+        };
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content =
+            std::fs::read_to_string(&path).map_err(|e| ConfigError::Io(path.clone(), e))?;
+        serde_json::from_str(&content).map_err(|e| ConfigError::Parse(path, e))
+    }
+}
+
+pub fn get_config_dir() -> Option<PathBuf> {
+    directories::ProjectDirs::from(QUALIFIER, ORG_NAME, APP_NAME)
+        .map(|dirs| dirs.config_dir().to_path_buf())
+}
+
+impl Config {
+    /// Label for a lesson type. Types without configured label are shown as is.
+    pub fn lesson_type_label(&self, lesson_type: &LessonType) -> LessonTypeLabel {
+        self.lesson_types
+            .get(lesson_type)
+            .cloned()
+            .unwrap_or_else(|| LessonTypeLabel {
+                short: lesson_type.to_string(),
+                full: lesson_type.to_string(),
+            })
+    }
+}
+
+pub fn get_data_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os(DATA_DIR_ENV) {
+        return PathBuf::from(dir);
+    }
+    directories::ProjectDirs::from(QUALIFIER, ORG_NAME, APP_NAME)
+        .expect("No valid data directory could be retrieved from the operating system")
+        .data_dir()
+        .to_path_buf()
+}
+
+impl Exams {
+    /// Duration of an exam event in minutes.
+    pub fn duration(&self, department: &str, event_type: &ExamType) -> u32 {
+        self.department_durations
+            .get(department)
+            .and_then(|durations| durations.get(event_type))
+            .or_else(|| self.durations.get(event_type))
+            .copied()
+            .unwrap_or(self.default_duration)
+    }
 }
 
 impl Default for Config {
@@ -31,7 +167,67 @@ impl Default for Config {
             semester: Semester {
                 start_md: (2, 6),
                 end_md: (5, 31),
+                holidays: Vec::new(),
             },
+            exams: Exams::default(),
+            lesson_types: default_lesson_types(),
+            templates: Templates::default(),
+            places: HashMap::new(),
+            alarms: AlarmLimits::default(),
+            webhooks: WebhooksConfig::default(),
+            crawl: CrawlConfig::default(),
+            subgroups: SubgroupsConfig::default(),
+        }
+    }
+}
+
+fn default_lesson_types() -> HashMap<LessonType, LessonTypeLabel> {
+    [
+        (LessonType::Lecture, "Л", "Лекция"),
+        (LessonType::Practice, "П", "Практика"),
+        (LessonType::Laboratory, "Лаб", "Лабораторная работа"),
+        (LessonType::Seminar, "С", "Семинар"),
+    ]
+    .into_iter()
+    .map(|(lesson_type, short, full)| {
+        let label = LessonTypeLabel {
+            short: short.to_string(),
+            full: full.to_string(),
+        };
+        (lesson_type, label)
+    })
+    .collect()
+}
+
+impl Default for Exams {
+    fn default() -> Self {
+        let durations = [
+            (ExamType::Consultation, 90),
+            (ExamType::Exam, 240),
+            (ExamType::Midterm, 120),
+            (ExamType::MidtermWithMark, 120),
+        ];
+        let styles = [
+            (ExamType::Consultation, "Консультация", "lightskyblue"),
+            (ExamType::Exam, "Экзамен", "crimson"),
+            (ExamType::Midterm, "Зачет", "gold"),
+            (ExamType::MidtermWithMark, "Зачет с оценкой", "orange"),
+        ];
+
+        Self {
+            durations: durations.into_iter().collect(),
+            department_durations: HashMap::new(),
+            default_duration: 120,
+            styles: styles
+                .into_iter()
+                .map(|(t, category, color)| {
+                    let style = ExamStyle {
+                        category: category.to_string(),
+                        color: color.to_string(),
+                    };
+                    (t, style)
+                })
+                .collect(),
         }
     }
 }
@@ -0,0 +1,227 @@
+use crate::{
+    bells,
+    calendar::Combined,
+    models::{ExamList, Schedule},
+    server, snapshot,
+    tracto::{self, RequestResult},
+    Config, Request,
+};
+
+use clap::Parser;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::{fmt, future::Future, time::Duration};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CrawlConfig {
+    /// Hours between crawls done by the server, 0 disables crawling
+    pub interval_hours: u64,
+    /// Number of groups fetched simultaneously
+    pub concurrency: usize,
+    /// Delay before every request to Tracto
+    pub delay_ms: u64,
+    pub forms: Vec<String>,
+}
+
+impl Default for CrawlConfig {
+    fn default() -> Self {
+        Self {
+            interval_hours: 0,
+            concurrency: 4,
+            delay_ms: 500,
+            forms: vec![String::from("full"), String::from("extramural")],
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+pub struct CrawlArgs {
+    /// Number of groups fetched simultaneously
+    #[arg(short, long)]
+    pub concurrency: Option<usize>,
+    /// Delay in milliseconds before every request to Tracto
+    #[arg(long)]
+    pub delay_ms: Option<u64>,
+    /// Only crawl these departments
+    #[arg(short, long, num_args(0..))]
+    pub departments: Vec<String>,
+    /// Only crawl these education forms
+    #[arg(short, long, num_args(0..))]
+    pub forms: Vec<String>,
+}
+
+pub struct CrawlOptions {
+    pub concurrency: usize,
+    pub delay: Duration,
+    pub departments: Vec<String>,
+    pub forms: Vec<String>,
+}
+
+impl From<&CrawlConfig> for CrawlOptions {
+    fn from(cfg: &CrawlConfig) -> Self {
+        Self {
+            concurrency: cfg.concurrency.max(1),
+            delay: Duration::from_millis(cfg.delay_ms),
+            departments: Vec::new(),
+            forms: cfg.forms.clone(),
+        }
+    }
+}
+
+impl CrawlOptions {
+    pub fn new(cfg: &CrawlConfig, args: CrawlArgs) -> Self {
+        let mut options = Self::from(cfg);
+        if let Some(concurrency) = args.concurrency {
+            options.concurrency = concurrency.max(1);
+        }
+        if let Some(delay_ms) = args.delay_ms {
+            options.delay = Duration::from_millis(delay_ms);
+        }
+        options.departments = args.departments;
+        if !args.forms.is_empty() {
+            options.forms = args.forms;
+        }
+        options
+    }
+}
+
+/// Crawl results of one department.
+#[derive(Debug, Default)]
+pub struct DepartmentReport {
+    pub department: String,
+    pub succeeded: Vec<String>,
+    /// Groups (or forms, if the group list failed) with errors
+    pub failed: Vec<(String, String)>,
+}
+
+impl fmt::Display for DepartmentReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} ok, {} failed",
+            self.department,
+            self.succeeded.len(),
+            self.failed.len()
+        )?;
+        for (group, error) in &self.failed {
+            write!(f, "\n    {group}: {error}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Fetches schedule and exams of a group, storing snapshots and
+/// the default calendars in the cache.
+async fn crawl_group(cfg: &Config, req: &Request, delay: Duration) -> RequestResult<()> {
+    actix_web::rt::time::sleep(delay).await;
+    let schedule = tracto::fetch_schedule(cfg, req).await?;
+    snapshot::record_fetched(req, &schedule).await;
+    server::save_to_cache::<Schedule>(req, schedule.to_ical(cfg, req))?;
+
+    actix_web::rt::time::sleep(delay).await;
+    match tracto::fetch_exam(cfg, req).await {
+        Ok(exams) => {
+            snapshot::record_fetched(req, &exams).await;
+            server::save_to_cache::<ExamList>(req, exams.to_ical(cfg, req))?;
+            let combined_req = Request {
+                exams: true,
+                ..req.clone()
+            };
+            let combined = Combined { schedule, exams };
+            server::save_to_cache::<Combined>(&combined_req, combined.to_ical(cfg, &combined_req))?;
+        }
+        // Not every group has exams, which is not a crawl failure
+        Err(e) => log::warn!(
+            "No exams for {}/{}/{}: {e}",
+            req.department,
+            req.form,
+            req.group
+        ),
+    }
+
+    Ok(())
+}
+
+/// Fetches every department and group, see `CrawlOptions`.
+pub async fn crawl(cfg: &Config, options: &CrawlOptions) -> RequestResult<Vec<DepartmentReport>> {
+    let reports = walk(cfg, options, |req| async move {
+        crawl_group(cfg, &req, options.delay).await
+    })
+    .await?;
+
+    for report in reports.iter().filter(|report| !report.succeeded.is_empty()) {
+        let department = report.department.clone();
+        let bells =
+            actix_web::rt::task::spawn_blocking(move || bells::department_report(&department))
+                .await;
+        match bells {
+            Ok(Ok(bells)) => bells::warn_mismatches(&bells),
+            Ok(Err(e)) => log::error!("Cannot check bells of {}: {e}", report.department),
+            Err(e) => log::error!("Cannot check bells of {}: {e}", report.department),
+        }
+    }
+    Ok(reports)
+}
+
+/// Calls `visit` for every department and group, see `CrawlOptions`.
+pub async fn walk<F, Fut>(
+    cfg: &Config,
+    options: &CrawlOptions,
+    visit: F,
+) -> RequestResult<Vec<DepartmentReport>>
+where
+    F: Fn(Request) -> Fut,
+    Fut: Future<Output = RequestResult<()>>,
+{
+    let departments = tracto::fetch_departments(cfg).await?.departments_list;
+
+    let mut reports = Vec::new();
+    for department in departments {
+        if !options.departments.is_empty() && !options.departments.contains(&department.url) {
+            continue;
+        }
+
+        let mut report = DepartmentReport {
+            department: department.url.clone(),
+            ..Default::default()
+        };
+        for form in &options.forms {
+            let groups = match tracto::fetch_groups(cfg, &department.url, form).await {
+                Ok(groups) => groups.groups_list,
+                Err(e) => {
+                    report.failed.push((form.clone(), e.to_string()));
+                    continue;
+                }
+            };
+
+            let requests = groups.into_iter().map(|group| Request {
+                department: department.url.clone(),
+                form: form.clone(),
+                group: group.group_number,
+                ..Default::default()
+            });
+            let mut results = futures::stream::iter(requests)
+                .map(|req| {
+                    let group = req.group.clone();
+                    let visited = visit(req);
+                    async move { (group, visited.await) }
+                })
+                .buffer_unordered(options.concurrency);
+
+            while let Some((group, result)) = results.next().await {
+                match result {
+                    Ok(()) => report.succeeded.push(format!("{form}/{group}")),
+                    Err(e) => report
+                        .failed
+                        .push((format!("{form}/{group}"), e.to_string())),
+                }
+            }
+        }
+
+        log::info!("Crawled {report}");
+        reports.push(report);
+    }
+
+    Ok(reports)
+}
@@ -0,0 +1,634 @@
+use crate::{config, models::*};
+
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Schema migrations, `PRAGMA user_version` holds the number of applied ones.
+const MIGRATIONS: &[&str] = &[
+    r#"
+    CREATE TABLE departments (
+        id INTEGER PRIMARY KEY,
+        url TEXT NOT NULL,
+        short_name TEXT NOT NULL,
+        full_name TEXT NOT NULL
+    );
+    CREATE TABLE student_groups (
+        id INTEGER PRIMARY KEY,
+        department_id INTEGER NOT NULL REFERENCES departments(id),
+        group_number TEXT NOT NULL,
+        group_number_rus TEXT NOT NULL,
+        education_form TEXT NOT NULL,
+        group_type TEXT NOT NULL
+    );
+    CREATE TABLE teachers (
+        id INTEGER PRIMARY KEY,
+        surname TEXT NOT NULL,
+        name TEXT NOT NULL,
+        patronymic TEXT NOT NULL
+    );
+    -- Shared lectures have the same id in every group
+    CREATE TABLE lessons (
+        id INTEGER NOT NULL,
+        group_id INTEGER NOT NULL REFERENCES student_groups(id),
+        teacher_id INTEGER NOT NULL REFERENCES teachers(id),
+        name TEXT NOT NULL,
+        place TEXT NOT NULL,
+        sub_group TEXT NOT NULL,
+        day_number INTEGER NOT NULL,
+        lesson_number INTEGER NOT NULL,
+        hour_start INTEGER NOT NULL,
+        minute_start INTEGER NOT NULL,
+        hour_end INTEGER NOT NULL,
+        minute_end INTEGER NOT NULL,
+        week_type TEXT NOT NULL,
+        lesson_type TEXT NOT NULL,
+        updated_timestamp INTEGER NOT NULL,
+        PRIMARY KEY (id, group_id)
+    );
+    CREATE INDEX lessons_group ON lessons(group_id);
+    CREATE INDEX lessons_teacher ON lessons(teacher_id);
+    CREATE TABLE exams (
+        id INTEGER NOT NULL,
+        group_id INTEGER NOT NULL REFERENCES student_groups(id),
+        teacher_id INTEGER NOT NULL REFERENCES teachers(id),
+        exam_type TEXT NOT NULL,
+        date TEXT NOT NULL,
+        hour INTEGER NOT NULL,
+        minute INTEGER NOT NULL,
+        subject_name TEXT NOT NULL,
+        place TEXT NOT NULL,
+        PRIMARY KEY (id, group_id)
+    );
+    CREATE INDEX exams_group ON exams(group_id);
+    CREATE TABLE snapshots (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        kind TEXT NOT NULL,
+        department TEXT NOT NULL,
+        form TEXT NOT NULL,
+        group_name TEXT NOT NULL,
+        taken_at INTEGER NOT NULL,
+        payload TEXT NOT NULL
+    );
+    CREATE INDEX snapshots_group ON snapshots(kind, department, form, group_name, taken_at);
+    CREATE TABLE fetches (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        snapshot_id INTEGER NOT NULL REFERENCES snapshots(id) ON DELETE CASCADE,
+        fetched_at INTEGER NOT NULL
+    );
+    CREATE INDEX fetches_snapshot ON fetches(snapshot_id);
+"#,
+    r#"
+    CREATE TABLE subscriptions (
+        token TEXT PRIMARY KEY,
+        secret_hash TEXT NOT NULL,
+        params TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        expires_at INTEGER,
+        hits INTEGER NOT NULL DEFAULT 0,
+        last_used_at INTEGER
+    );
+"#,
+    r#"
+    ALTER TABLE subscriptions ADD COLUMN overrides TEXT NOT NULL DEFAULT '{}';
+"#,
+    r#"
+    CREATE TABLE webhooks (
+        id TEXT PRIMARY KEY,
+        url TEXT NOT NULL,
+        secret TEXT NOT NULL,
+        department TEXT NOT NULL,
+        form TEXT NOT NULL,
+        group_name TEXT NOT NULL,
+        subgroups TEXT NOT NULL,
+        schedule_seen INTEGER,
+        exams_seen INTEGER
+    );
+    CREATE TABLE webhook_deliveries (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        webhook_id TEXT NOT NULL REFERENCES webhooks(id) ON DELETE CASCADE,
+        timestamp TEXT NOT NULL,
+        attempt INTEGER NOT NULL,
+        status INTEGER,
+        error TEXT
+    );
+    CREATE INDEX webhook_deliveries_webhook ON webhook_deliveries(webhook_id, id);
+"#,
+];
+
+/// Group which snapshots belong to.
+pub struct GroupKey<'a> {
+    pub department: &'a str,
+    pub form: &'a str,
+    pub group: &'a str,
+}
+
+/// Tracto data kept in the database: as raw snapshots and normalized
+/// into tables.
+pub trait Stored: Serialize + DeserializeOwned + PartialEq {
+    const KIND: &'static str;
+
+    fn store_normalized(&self, tx: &Transaction) -> rusqlite::Result<()>;
+}
+
+/// Opens the database in the data directory, applying pending migrations.
+pub fn open() -> rusqlite::Result<Connection> {
+    let dir = config::get_data_dir();
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        log::error!("Cannot create data directory {}: {e}", dir.display());
+    }
+
+    let mut conn = Connection::open(dir.join("calar.db"))?;
+    conn.busy_timeout(std::time::Duration::from_secs(5))?;
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    migrate(&mut conn)?;
+
+    Ok(conn)
+}
+
+/// Applies pending migrations. Returns the schema version.
+pub fn migrate(conn: &mut Connection) -> rusqlite::Result<usize> {
+    conn.pragma_update(None, "foreign_keys", true)?;
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+        log::info!("Applied database migration {}", i + 1);
+    }
+
+    Ok(MIGRATIONS.len())
+}
+
+fn upsert_department(tx: &Transaction, department: &Department) -> rusqlite::Result<()> {
+    tx.execute(
+        "INSERT INTO departments (id, url, short_name, full_name)
+         VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT (id) DO UPDATE SET
+             url = excluded.url,
+             short_name = excluded.short_name,
+             full_name = excluded.full_name",
+        params![
+            department.id,
+            department.url,
+            department.short_name,
+            department.full_name
+        ],
+    )?;
+    Ok(())
+}
+
+fn upsert_group(tx: &Transaction, group: &StudentGroup) -> rusqlite::Result<()> {
+    upsert_department(tx, &group.department)?;
+    tx.execute(
+        "INSERT INTO student_groups
+         (id, department_id, group_number, group_number_rus, education_form, group_type)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+         ON CONFLICT (id) DO UPDATE SET
+             department_id = excluded.department_id,
+             group_number = excluded.group_number,
+             group_number_rus = excluded.group_number_rus,
+             education_form = excluded.education_form,
+             group_type = excluded.group_type",
+        params![
+            group.id,
+            group.department.id,
+            group.group_number,
+            group.group_number_rus,
+            group.education_form.as_str(),
+            group.group_type
+        ],
+    )?;
+    Ok(())
+}
+
+fn upsert_teacher(tx: &Transaction, teacher: &Teacher) -> rusqlite::Result<()> {
+    tx.execute(
+        "INSERT INTO teachers (id, surname, name, patronymic)
+         VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT (id) DO UPDATE SET
+             surname = excluded.surname,
+             name = excluded.name,
+             patronymic = excluded.patronymic",
+        params![
+            teacher.id,
+            teacher.surname,
+            teacher.name,
+            teacher.patronymic
+        ],
+    )?;
+    Ok(())
+}
+
+impl Stored for Schedule {
+    const KIND: &'static str = "Schedule";
+
+    fn store_normalized(&self, tx: &Transaction) -> rusqlite::Result<()> {
+        upsert_group(tx, &self.student_group)?;
+        tx.execute(
+            "DELETE FROM lessons WHERE group_id = ?1",
+            [self.student_group.id],
+        )?;
+
+        for lesson in &self.lessons {
+            upsert_teacher(tx, &lesson.teacher)?;
+            let time = &lesson.lesson_time;
+            tx.execute(
+                "INSERT INTO lessons
+                 (id, group_id, teacher_id, name, place, sub_group, day_number,
+                  lesson_number, hour_start, minute_start, hour_end, minute_end,
+                  week_type, lesson_type, updated_timestamp)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+                params![
+                    lesson.id,
+                    self.student_group.id,
+                    lesson.teacher.id,
+                    lesson.name,
+                    lesson.place,
+                    lesson.sub_group.trim(),
+                    lesson.day.day_number,
+                    time.lesson_number,
+                    time.hour_start,
+                    time.minute_start,
+                    time.hour_end,
+                    time.minute_end,
+                    lesson.week_type.as_str(),
+                    lesson.lesson_type.as_str(),
+                    lesson.updated_timestamp
+                ],
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Stored for ExamList {
+    const KIND: &'static str = "ExamList";
+
+    fn store_normalized(&self, tx: &Transaction) -> rusqlite::Result<()> {
+        upsert_group(tx, &self.student_group)?;
+        tx.execute(
+            "DELETE FROM exams WHERE group_id = ?1",
+            [self.student_group.id],
+        )?;
+
+        for exam in &self.exam_period_events {
+            upsert_teacher(tx, &exam.teacher)?;
+            let date = format!(
+                "{}-{:02}-{:02}",
+                exam.year.replace("г.", "").trim(),
+                exam.month.number,
+                exam.day
+            );
+            tx.execute(
+                "INSERT INTO exams
+                 (id, group_id, teacher_id, exam_type, date, hour, minute, subject_name, place)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    exam.id,
+                    self.student_group.id,
+                    exam.teacher.id,
+                    exam.exam_period_event_type.as_str(),
+                    date,
+                    exam.hour,
+                    exam.minute,
+                    exam.subject_name,
+                    exam.place
+                ],
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Records a fetch of `data`. A new snapshot is stored, and normalized
+/// tables are updated, only if data differs from the latest snapshot.
+/// Returns whether a new snapshot was stored.
+pub fn record<T: Stored>(
+    conn: &mut Connection,
+    key: &GroupKey,
+    data: &T,
+    now: i64,
+) -> rusqlite::Result<bool> {
+    let tx = conn.transaction()?;
+    let latest: Option<(i64, String)> = tx
+        .query_row(
+            "SELECT id, payload FROM snapshots
+             WHERE kind = ?1 AND department = ?2 AND form = ?3 AND group_name = ?4
+             ORDER BY taken_at DESC, id DESC LIMIT 1",
+            params![T::KIND, key.department, key.form, key.group],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+
+    // Payloads are compared as stored, parsing them again would count
+    // unknown Tracto values once more
+    let payload = serde_json::to_string(data)
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+    let unchanged = latest
+        .as_ref()
+        .and_then(|(id, latest)| (*latest == payload).then_some(*id));
+    let snapshot_id = match unchanged {
+        Some(id) => id,
+        None => {
+            tx.execute(
+                "INSERT INTO snapshots (kind, department, form, group_name, taken_at, payload)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![T::KIND, key.department, key.form, key.group, now, payload],
+            )?;
+            let id = tx.last_insert_rowid();
+            data.store_normalized(&tx)?;
+            id
+        }
+    };
+    tx.execute(
+        "INSERT INTO fetches (snapshot_id, fetched_at) VALUES (?1, ?2)",
+        params![snapshot_id, now],
+    )?;
+    tx.commit()?;
+
+    Ok(unchanged.is_none())
+}
+
+/// All snapshots of a group as (timestamp, data), oldest first.
+pub fn load_snapshots<T: Stored>(
+    conn: &Connection,
+    key: &GroupKey,
+) -> rusqlite::Result<Vec<(i64, T)>> {
+    let mut stmt = conn.prepare(
+        "SELECT taken_at, payload FROM snapshots
+         WHERE kind = ?1 AND department = ?2 AND form = ?3 AND group_name = ?4
+         ORDER BY taken_at, id",
+    )?;
+    let rows = stmt.query_map(
+        params![T::KIND, key.department, key.form, key.group],
+        |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)),
+    )?;
+
+    let mut snapshots = Vec::new();
+    for row in rows {
+        let (taken_at, payload) = row?;
+        match serde_json::from_str(&payload) {
+            Ok(data) => snapshots.push((taken_at, data)),
+            Err(e) => log::error!("Cannot parse {} snapshot: {e}", T::KIND),
+        }
+    }
+
+    Ok(snapshots)
+}
+
+fn query_snapshot<T: Stored>(
+    conn: &Connection,
+    key: &GroupKey,
+    condition_and_order: &str,
+    moment: i64,
+) -> rusqlite::Result<Option<(i64, T)>> {
+    let snapshot: Option<(i64, String)> = conn
+        .query_row(
+            &format!(
+                "SELECT taken_at, payload FROM snapshots
+                 WHERE kind = ?1 AND department = ?2 AND form = ?3 AND group_name = ?4
+                 {condition_and_order} LIMIT 1"
+            ),
+            params![T::KIND, key.department, key.form, key.group, moment],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+
+    Ok(
+        snapshot.and_then(|(taken_at, payload)| match serde_json::from_str(&payload) {
+            Ok(data) => Some((taken_at, data)),
+            Err(e) => {
+                log::error!("Cannot parse {} snapshot: {e}", T::KIND);
+                None
+            }
+        }),
+    )
+}
+
+/// The newest snapshot of a group taken at `moment` or earlier, or the
+/// oldest one if all of them are newer, as (timestamp, data).
+pub fn load_snapshot_before<T: Stored>(
+    conn: &Connection,
+    key: &GroupKey,
+    moment: i64,
+) -> rusqlite::Result<Option<(i64, T)>> {
+    match query_snapshot(
+        conn,
+        key,
+        "AND taken_at <= ?5 ORDER BY taken_at DESC, id DESC",
+        moment,
+    )? {
+        Some(snapshot) => Ok(Some(snapshot)),
+        None => query_snapshot(conn, key, "AND taken_at > ?5 ORDER BY taken_at, id", moment),
+    }
+}
+
+/// Groups the teacher has lessons with, as (department, form, group)
+/// the way requests name them.
+pub fn teacher_groups(
+    conn: &Connection,
+    teacher_id: u32,
+) -> rusqlite::Result<Vec<(String, String, String)>> {
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT d.url, lower(g.education_form), g.group_number
+         FROM lessons l
+         JOIN student_groups g ON g.id = l.group_id
+         JOIN departments d ON d.id = g.department_id
+         WHERE l.teacher_id = ?1
+         ORDER BY 1, 2, 3",
+    )?;
+    let rows = stmt.query_map([teacher_id], |row| {
+        Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+    })?;
+    rows.collect()
+}
+
+/// Groups of the department having schedule snapshots, as (form, group).
+pub fn department_groups(
+    conn: &Connection,
+    department: &str,
+) -> rusqlite::Result<Vec<(String, String)>> {
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT form, group_name FROM snapshots
+         WHERE kind = ?1 AND department = ?2
+         ORDER BY 1, 2",
+    )?;
+    let rows = stmt.query_map(params![Schedule::KIND, department], |row| {
+        Ok((row.get(0)?, row.get(1)?))
+    })?;
+    rows.collect()
+}
+
+/// Row counts of every table.
+pub fn stats(conn: &Connection) -> rusqlite::Result<Vec<(&'static str, i64)>> {
+    [
+        "departments",
+        "student_groups",
+        "teachers",
+        "lessons",
+        "exams",
+        "snapshots",
+        "fetches",
+        "subscriptions",
+        "webhooks",
+        "webhook_deliveries",
+    ]
+    .into_iter()
+    .map(|table| {
+        let count = conn.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+            row.get(0)
+        })?;
+        Ok((table, count))
+    })
+    .collect()
+}
+
+/// Keeps only `keep` latest snapshots of every group.
+/// Returns the number of deleted snapshots.
+pub fn prune_snapshots(conn: &Connection, keep: usize) -> rusqlite::Result<usize> {
+    conn.execute(
+        "DELETE FROM snapshots WHERE id IN (
+             SELECT id FROM (
+                 SELECT id, ROW_NUMBER() OVER (
+                     PARTITION BY kind, department, form, group_name
+                     ORDER BY taken_at DESC, id DESC
+                 ) AS n
+                 FROM snapshots
+             ) WHERE n > ?1
+         )",
+        [keep as i64],
+    )
+}
+
+/// Keeps only `keep` latest fetch records of every group, as every
+/// fetch adds one even if nothing changed.
+/// Returns the number of deleted records.
+pub fn prune_fetches(conn: &Connection, keep: usize) -> rusqlite::Result<usize> {
+    conn.execute(
+        "DELETE FROM fetches WHERE id IN (
+             SELECT id FROM (
+                 SELECT f.id, ROW_NUMBER() OVER (
+                     PARTITION BY s.kind, s.department, s.form, s.group_name
+                     ORDER BY f.fetched_at DESC, f.id DESC
+                 ) AS n
+                 FROM fetches f JOIN snapshots s ON s.id = f.snapshot_id
+             ) WHERE n > ?1
+         )",
+        [keep as i64],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::*;
+
+    fn test_db() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        conn
+    }
+
+    const KEY: GroupKey = GroupKey {
+        department: "knt",
+        form: "full",
+        group: "351",
+    };
+
+    #[test]
+    fn migrations_are_idempotent() {
+        let mut conn = test_db();
+        assert_eq!(migrate(&mut conn).unwrap(), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn record_does_not_count_unknown_values_again() {
+        let mut conn = test_db();
+        let mut lesson = lesson_json(1, 1, "FULL", "");
+        lesson["lessonType"] = "COLLOQUIUM".into();
+        let data = schedule(vec![lesson]);
+        let count = || crate::models::unknown_values()["LessonType"]["COLLOQUIUM"];
+        assert_eq!(count(), 1);
+
+        assert!(record(&mut conn, &KEY, &data, 100).unwrap());
+        assert!(!record(&mut conn, &KEY, &data, 200).unwrap());
+        assert_eq!(count(), 1);
+    }
+
+    #[test]
+    fn record_deduplicates_snapshots() {
+        let mut conn = test_db();
+        let old = schedule(vec![
+            lesson_json(1, 1, "FULL", ""),
+            lesson_json(2, 2, "NOM", ""),
+        ]);
+        let new = schedule(vec![lesson_json(1, 1, "FULL", "")]);
+
+        assert!(record(&mut conn, &KEY, &old, 100).unwrap());
+        assert!(!record(&mut conn, &KEY, &old, 200).unwrap());
+        assert!(record(&mut conn, &KEY, &new, 300).unwrap());
+
+        let snapshots = load_snapshots::<Schedule>(&conn, &KEY).unwrap();
+        assert_eq!(
+            snapshots.iter().map(|(ts, _)| *ts).collect::<Vec<_>>(),
+            [100, 300]
+        );
+        assert_eq!(snapshots[1].1, new);
+        let before = |moment| {
+            load_snapshot_before::<Schedule>(&conn, &KEY, moment)
+                .unwrap()
+                .map(|(ts, _)| ts)
+        };
+        assert_eq!(
+            [before(50), before(200), before(i64::MAX)],
+            [Some(100), Some(100), Some(300)]
+        );
+
+        let counts: std::collections::HashMap<_, _> = stats(&conn).unwrap().into_iter().collect();
+        assert_eq!(counts["lessons"], 1);
+        assert_eq!(counts["fetches"], 3);
+        assert_eq!(
+            teacher_groups(&conn, 1).unwrap(),
+            [("knt".to_string(), "full".to_string(), "351".to_string())]
+        );
+        assert!(teacher_groups(&conn, 2).unwrap().is_empty());
+        assert_eq!(
+            department_groups(&conn, "knt").unwrap(),
+            [("full".to_string(), "351".to_string())]
+        );
+
+        assert_eq!(prune_snapshots(&conn, 1).unwrap(), 1);
+        let counts: std::collections::HashMap<_, _> = stats(&conn).unwrap().into_iter().collect();
+        assert_eq!(counts["snapshots"], 1);
+        assert_eq!(counts["fetches"], 1);
+
+        for now in [310, 320, 330] {
+            assert!(!record(&mut conn, &KEY, &new, now).unwrap());
+        }
+        assert_eq!(prune_fetches(&conn, 2).unwrap(), 2);
+        let counts: std::collections::HashMap<_, _> = stats(&conn).unwrap().into_iter().collect();
+        assert_eq!(counts["fetches"], 2);
+
+        // The same lecture of another group is kept for both groups
+        let mut other = new;
+        other.student_group.id += 1;
+        other.student_group.group_number = "352".to_string();
+        let other_key = GroupKey {
+            group: "352",
+            ..KEY
+        };
+        assert!(record(&mut conn, &other_key, &other, 400).unwrap());
+        assert!(record(&mut conn, &KEY, &old, 500).unwrap());
+        assert_eq!(
+            teacher_groups(&conn, 1).unwrap(),
+            [
+                ("knt".to_string(), "full".to_string(), "351".to_string()),
+                ("knt".to_string(), "full".to_string(), "352".to_string())
+            ]
+        );
+    }
+}
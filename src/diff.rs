@@ -0,0 +1,343 @@
+use crate::{models::*, snapshot, tracto::RequestResult, Request};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt};
+
+const WEEKDAYS: [&str; 7] = ["Пн", "Вт", "Ср", "Чт", "Пт", "Сб", "Вс"];
+
+/// Short lesson description used in diffs.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LessonInfo {
+    pub id: u32,
+    pub name: String,
+    pub lesson_type: LessonType,
+    pub week_type: WeekType,
+    pub sub_group: String,
+    pub day: String,
+    pub time: String,
+    pub place: String,
+    pub teacher: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub old: String,
+    pub new: String,
+}
+
+/// Short exam event description used in diffs.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExamInfo {
+    pub id: u32,
+    pub name: String,
+    pub exam_type: ExamType,
+    pub date: String,
+    pub time: String,
+    pub place: String,
+    pub teacher: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Change<T> {
+    pub item: T,
+    pub changes: Vec<FieldChange>,
+}
+
+/// Semantic difference of two lists keyed by Tracto id.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diff<T> {
+    pub added: Vec<T>,
+    pub removed: Vec<T>,
+    pub changed: Vec<Change<T>>,
+}
+
+pub type ScheduleDiff = Diff<LessonInfo>;
+pub type ExamDiff = Diff<ExamInfo>;
+
+/// Item which can be compared field by field.
+pub trait DiffItem: Clone {
+    fn id(&self) -> u32;
+    fn changes(&self, new: &Self) -> Vec<FieldChange>;
+}
+
+pub fn weekday_name(day_number: u32) -> &'static str {
+    WEEKDAYS
+        .get((day_number as usize).wrapping_sub(1))
+        .copied()
+        .unwrap_or("?")
+}
+
+/// Changes of a group schedule since some moment.
+#[derive(Debug, Serialize)]
+pub struct Changes {
+    /// When the snapshot compared against was taken, RFC 3339
+    pub since: String,
+    pub diff: ScheduleDiff,
+}
+
+/// Stores the `current` schedule as a snapshot and compares it
+/// to the newest snapshot taken before `moment`.
+pub async fn changes_since(
+    req: &Request,
+    current: Schedule,
+    moment: DateTime<Utc>,
+) -> RequestResult<Changes> {
+    snapshot::record_fetched(req, &current).await;
+    let base = snapshot::load_before::<Schedule>(req, moment)?.unwrap_or(snapshot::Snapshot {
+        taken_at: Utc::now(),
+        data: current.clone(),
+    });
+
+    Ok(Changes {
+        since: base.taken_at.to_rfc3339(),
+        diff: ScheduleDiff::new(&base.data, &current),
+    })
+}
+
+impl From<&Lesson> for LessonInfo {
+    fn from(lesson: &Lesson) -> Self {
+        let time = &lesson.lesson_time;
+        Self {
+            id: lesson.id,
+            name: lesson.name.clone(),
+            lesson_type: lesson.lesson_type.clone(),
+            week_type: lesson.week_type.clone(),
+            sub_group: lesson.sub_group.trim().to_string(),
+            day: weekday_name(lesson.day.day_number).to_string(),
+            time: format!(
+                "{:02}:{:02}-{:02}:{:02}",
+                time.hour_start, time.minute_start, time.hour_end, time.minute_end
+            ),
+            place: lesson.place.clone(),
+            teacher: lesson.teacher.full(),
+        }
+    }
+}
+
+fn field_changes(fields: &[(&'static str, String, String)]) -> Vec<FieldChange> {
+    fields
+        .iter()
+        .filter(|(_, old, new)| old != new)
+        .map(|(field, old, new)| FieldChange {
+            field,
+            old: old.clone(),
+            new: new.clone(),
+        })
+        .collect()
+}
+
+impl DiffItem for LessonInfo {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn changes(&self, new: &LessonInfo) -> Vec<FieldChange> {
+        field_changes(&[
+            ("name", self.name.clone(), new.name.clone()),
+            ("day", self.day.clone(), new.day.clone()),
+            ("time", self.time.clone(), new.time.clone()),
+            ("place", self.place.clone(), new.place.clone()),
+            ("teacher", self.teacher.clone(), new.teacher.clone()),
+            ("sub_group", self.sub_group.clone(), new.sub_group.clone()),
+            (
+                "week_type",
+                self.week_type.to_string(),
+                new.week_type.to_string(),
+            ),
+            (
+                "lesson_type",
+                self.lesson_type.to_string(),
+                new.lesson_type.to_string(),
+            ),
+        ])
+    }
+}
+
+impl From<&ExamEvent> for ExamInfo {
+    fn from(exam: &ExamEvent) -> Self {
+        Self {
+            id: exam.id,
+            name: exam.subject_name.clone(),
+            exam_type: exam.exam_period_event_type.clone(),
+            date: format!(
+                "{:02}.{:02}.{}",
+                exam.day,
+                exam.month.number,
+                exam.year.replace("г.", "").trim()
+            ),
+            time: format!("{:02}:{:02}", exam.hour, exam.minute),
+            place: exam.place.clone(),
+            teacher: exam.teacher.full(),
+        }
+    }
+}
+
+impl DiffItem for ExamInfo {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn changes(&self, new: &ExamInfo) -> Vec<FieldChange> {
+        field_changes(&[
+            ("name", self.name.clone(), new.name.clone()),
+            (
+                "exam_type",
+                self.exam_type.to_string(),
+                new.exam_type.to_string(),
+            ),
+            ("date", self.date.clone(), new.date.clone()),
+            ("time", self.time.clone(), new.time.clone()),
+            ("place", self.place.clone(), new.place.clone()),
+            ("teacher", self.teacher.clone(), new.teacher.clone()),
+        ])
+    }
+}
+
+impl<T> Default for Diff<T> {
+    fn default() -> Self {
+        Self {
+            added: Vec::new(),
+            removed: Vec::new(),
+            changed: Vec::new(),
+        }
+    }
+}
+
+impl<T: DiffItem> Diff<T> {
+    pub fn from_items(old: &[T], new: &[T]) -> Self {
+        let old: BTreeMap<u32, &T> = old.iter().map(|item| (item.id(), item)).collect();
+        let new: BTreeMap<u32, &T> = new.iter().map(|item| (item.id(), item)).collect();
+
+        let mut diff = Self::default();
+        for (id, item) in &new {
+            match old.get(id) {
+                None => diff.added.push((*item).clone()),
+                Some(old_item) => {
+                    let changes = old_item.changes(item);
+                    if !changes.is_empty() {
+                        diff.changed.push(Change {
+                            item: (*item).clone(),
+                            changes,
+                        });
+                    }
+                }
+            }
+        }
+        diff.removed = old
+            .into_iter()
+            .filter(|(id, _)| !new.contains_key(id))
+            .map(|(_, item)| item.clone())
+            .collect();
+
+        diff
+    }
+}
+
+impl<T> Diff<T> {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// Keeps only items matching `pred`.
+    pub fn retain(&mut self, pred: impl Fn(&T) -> bool) {
+        self.added.retain(&pred);
+        self.removed.retain(&pred);
+        self.changed.retain(|change| pred(&change.item));
+    }
+}
+
+impl ScheduleDiff {
+    pub fn new(old: &Schedule, new: &Schedule) -> Self {
+        let old: Vec<LessonInfo> = old.lessons.iter().map(LessonInfo::from).collect();
+        let new: Vec<LessonInfo> = new.lessons.iter().map(LessonInfo::from).collect();
+        Self::from_items(&old, &new)
+    }
+}
+
+impl ExamDiff {
+    pub fn new(old: &ExamList, new: &ExamList) -> Self {
+        let old: Vec<ExamInfo> = old.exam_period_events.iter().map(ExamInfo::from).collect();
+        let new: Vec<ExamInfo> = new.exam_period_events.iter().map(ExamInfo::from).collect();
+        Self::from_items(&old, &new)
+    }
+}
+
+impl fmt::Display for LessonInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} [{}] {}, {}",
+            self.day, self.time, self.name, self.week_type, self.place, self.teacher
+        )?;
+        if !self.sub_group.is_empty() {
+            write!(f, " ({})", self.sub_group)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ExamInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} [{}] {}, {}",
+            self.date, self.time, self.name, self.exam_type, self.place, self.teacher
+        )
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Diff<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No changes");
+        }
+        for lesson in &self.added {
+            writeln!(f, "+ {lesson}")?;
+        }
+        for lesson in &self.removed {
+            writeln!(f, "- {lesson}")?;
+        }
+        for change in &self.changed {
+            writeln!(f, "~ {}", change.item)?;
+            for field in &change.changes {
+                writeln!(f, "    {}: {} -> {}", field.field, field.old, field.new)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::*;
+
+    #[test]
+    fn added_removed_and_changed() {
+        let old = schedule(vec![
+            lesson_json(1, 1, "FULL", ""),
+            lesson_json(2, 2, "NOM", ""),
+            lesson_json(3, 3, "FULL", ""),
+        ]);
+        let mut new = schedule(vec![
+            lesson_json(1, 1, "FULL", ""),
+            lesson_json(2, 4, "DENOM", ""),
+            lesson_json(4, 5, "FULL", ""),
+        ]);
+        new.lessons[1].place = String::from("9 корпус 101");
+
+        let diff = ScheduleDiff::new(&old, &new);
+        assert_eq!(diff.added.iter().map(|l| l.id).collect::<Vec<_>>(), [4]);
+        assert_eq!(diff.removed.iter().map(|l| l.id).collect::<Vec<_>>(), [3]);
+        assert_eq!(diff.changed.len(), 1);
+
+        let fields: Vec<_> = diff.changed[0].changes.iter().map(|c| c.field).collect();
+        assert_eq!(fields, ["day", "place", "week_type"]);
+        assert_eq!(diff.changed[0].changes[0].old, "Вт");
+        assert_eq!(diff.changed[0].changes[0].new, "Чт");
+
+        assert!(ScheduleDiff::new(&new, &new).is_empty());
+    }
+}
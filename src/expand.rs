@@ -0,0 +1,221 @@
+use crate::{
+    calendar::{semester_end, semester_start},
+    config::Holiday,
+    models::Lesson,
+    Config,
+};
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate};
+use chrono_tz::Tz;
+
+/// Days weekly lessons are expanded over: the semester, or a part of
+/// it, without holidays.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Term {
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+    /// First and last days off
+    pub holidays: Vec<(NaiveDate, NaiveDate)>,
+}
+
+fn holiday_dates(holiday: &Holiday, year: i32) -> Option<(NaiveDate, NaiveDate)> {
+    let date = |(month, day): (u32, u32)| NaiveDate::from_ymd_opt(year, month, day);
+    let dates = date(holiday.start_md).zip(date(holiday.end_md.unwrap_or(holiday.start_md)));
+    if dates.is_none() {
+        log::warn!("Ignoring holiday {:?} with incorrect dates", holiday.name);
+    }
+    dates
+}
+
+impl Term {
+    pub fn new(cfg: &Config) -> Self {
+        let start = semester_start(cfg);
+        Self {
+            start,
+            end: semester_end(cfg),
+            holidays: cfg
+                .semester
+                .holidays
+                .iter()
+                .filter_map(|holiday| holiday_dates(holiday, start.year()))
+                .collect(),
+        }
+    }
+
+    /// Term ending at `last_day` at the latest, see `Request::stop_at_session`.
+    pub fn until(self, last_day: NaiveDate) -> Self {
+        Self {
+            end: self.end.min(last_day),
+            ..self
+        }
+    }
+
+    /// Part of the term from `from` to `to` inclusive.
+    pub fn between(self, from: NaiveDate, to: NaiveDate) -> Self {
+        Self {
+            start: self.start.max(from),
+            end: self.end.min(to),
+            ..self
+        }
+    }
+
+    pub fn is_holiday(&self, date: NaiveDate) -> bool {
+        self.holidays
+            .iter()
+            .any(|(first, last)| (*first..=*last).contains(&date))
+    }
+}
+
+/// Dates of `RRULE:FREQ=WEEKLY;INTERVAL=<interval>;UNTIL=<until>`
+/// starting at `first`.
+pub fn recurrence(
+    first: NaiveDate,
+    interval: u32,
+    until: NaiveDate,
+) -> impl Iterator<Item = NaiveDate> {
+    let step = Duration::weeks(interval.into());
+    std::iter::successors(Some(first), move |date| Some(*date + step))
+        .take_while(move |date| *date <= until)
+}
+
+/// Occurrences of a weekly lesson in a term.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Expansion {
+    /// Start and end of every occurrence
+    pub spans: Vec<(DateTime<Tz>, DateTime<Tz>)>,
+    /// Starts of occurrences falling on holidays, the EXDATE of the event
+    pub excluded: Vec<DateTime<Tz>>,
+}
+
+impl Lesson {
+    /// Dated occurrences of the lesson in `term`. Week parity always
+    /// comes from the semester start, whatever part of it `term` is.
+    pub fn expand(&self, cfg: &Config, term: &Term) -> Expansion {
+        let (first_start, first_end) = self.first_span(cfg);
+        let first = first_start.date_naive();
+
+        let mut expansion = Expansion::default();
+        for date in recurrence(first, self.interval(), term.end) {
+            if date < term.start {
+                continue;
+            }
+            let offset = date - first;
+            if term.is_holiday(date) {
+                expansion.excluded.push(first_start + offset);
+            } else {
+                expansion
+                    .spans
+                    .push((first_start + offset, first_end + offset));
+            }
+        }
+        expansion
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures::*, Request};
+
+    use chrono::NaiveDateTime;
+    use std::collections::BTreeMap;
+
+    /// Local starts of every event of the calendar, expanded from its
+    /// DTSTART, RRULE and EXDATE properties day by day, without `recurrence`.
+    fn expand_ical(ics: &str) -> BTreeMap<String, Vec<NaiveDateTime>> {
+        let local = |value: &str| NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").unwrap();
+        let unfolded = ics.replace("\r\n ", "");
+        let mut events = BTreeMap::new();
+        for event in unfolded.split("BEGIN:VEVENT").skip(1) {
+            let property = |name: &str| {
+                event.lines().find_map(|line| {
+                    let (key, value) = line.split_once(':')?;
+                    (key.split(';').next() == Some(name)).then(|| value.to_string())
+                })
+            };
+            let start = local(&property("DTSTART").unwrap());
+            let rrule = property("RRULE").unwrap();
+            let rule = |part: &str| {
+                rrule
+                    .split(';')
+                    .find_map(|kv| kv.strip_prefix(part)?.strip_prefix('='))
+                    .unwrap()
+                    .to_string()
+            };
+            let interval: u32 = rule("INTERVAL").parse().unwrap();
+            let until = local(&rule("UNTIL")).date();
+            let excluded: Vec<NaiveDateTime> = property("EXDATE")
+                .map(|dates| dates.split(',').map(local).collect())
+                .unwrap_or_default();
+
+            let period = 7 * i64::from(interval);
+            let starts = start
+                .date()
+                .iter_days()
+                .take_while(|date| *date <= until)
+                .filter(|date| (*date - start.date()).num_days() % period == 0)
+                .map(|date| date.and_time(start.time()))
+                .filter(|start| !excluded.contains(start))
+                .collect();
+            events.insert(property("UID").unwrap(), starts);
+        }
+        events
+    }
+
+    #[test]
+    fn expansion_matches_rrule_and_exdate() {
+        let mut cfg = Config::default();
+        let schedule = schedule(vec![
+            lesson_json(1, 1, "FULL", ""),
+            lesson_json(2, 2, "NOM", ""),
+            lesson_json(3, 3, "DENOM", ""),
+        ]);
+        // Two weeks off, starting on the third Monday of the semester
+        let (monday, _) = schedule.lessons[0].first_span(&cfg);
+        let monday = monday.date_naive();
+        let first = monday + Duration::weeks(2);
+        let last = first + Duration::days(13);
+        cfg.semester.holidays = vec![Holiday {
+            name: "Каникулы".to_string(),
+            start_md: (first.month(), first.day()),
+            end_md: Some((last.month(), last.day())),
+        }];
+        // The semester ends on the eleventh Monday, the UNTIL day itself
+        let end = monday + Duration::weeks(10);
+        cfg.semester.end_md = (end.month(), end.day());
+        let request = Request::default();
+
+        let ics = schedule.to_ical(&cfg, &request).to_string();
+        assert!(ics.contains("EXDATE;TZID=Europe/Saratov:"));
+        let expected = expand_ical(&ics);
+
+        let occurrences = schedule.occurrences(&cfg, &request);
+        let mut expanded: BTreeMap<String, Vec<NaiveDateTime>> = BTreeMap::new();
+        for occurrence in &occurrences {
+            expanded
+                .entry(occurrence.uid.clone())
+                .or_default()
+                .push(occurrence.start.naive_local());
+        }
+        assert_eq!(expanded, expected);
+
+        let mondays: Vec<NaiveDate> = [0, 1, 4, 5, 6, 7, 8, 9, 10]
+            .into_iter()
+            .map(|week| monday + Duration::weeks(week))
+            .collect();
+        let starts: Vec<NaiveDate> = expanded["lesson-1@calar"]
+            .iter()
+            .map(|start| start.date())
+            .collect();
+        assert_eq!(starts, mondays);
+
+        let term = Term::new(&cfg);
+        let excluded = |n: usize| schedule.lessons[n].expand(&cfg, &term).excluded.len();
+        assert_eq!([excluded(0), excluded(1), excluded(2)], [2, 1, 1]);
+
+        let week = Term::new(&cfg).between(first - Duration::weeks(1), first);
+        let spans = schedule.lessons[0].expand(&cfg, &week).spans;
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].0.date_naive(), first - Duration::weeks(1));
+    }
+}
@@ -0,0 +1,346 @@
+use crate::{
+    calendar::Combined,
+    crawl::{self, CrawlArgs, CrawlOptions, DepartmentReport},
+    models::{ExamList, Schedule},
+    subgroup,
+    tracto::{self, RequestResult},
+    Config, Request,
+};
+
+use clap::Parser;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Most subgroup combinations exported for a group.
+pub const MAX_COMBINATIONS: usize = 32;
+
+#[derive(Parser, Debug)]
+pub struct ExportArgs {
+    /// Directory to write the site to
+    #[arg(short, long)]
+    pub out: PathBuf,
+    #[command(flatten)]
+    pub crawl: CrawlArgs,
+}
+
+/// Exported calendars of one group, paths are relative to the site root.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GroupEntry {
+    pub department: String,
+    pub department_name: String,
+    pub form: String,
+    pub group: String,
+    /// Lessons of every subgroup
+    pub calendar: String,
+    /// Lessons of a student picking one subgroup of every exclusive
+    /// category, keyed by the picked subgroups
+    pub combinations: BTreeMap<String, String>,
+    pub exams: Option<String>,
+    pub all: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct ExportReport {
+    pub departments: Vec<DepartmentReport>,
+    pub written: usize,
+    pub unchanged: usize,
+}
+
+#[derive(Default)]
+struct Progress {
+    entries: Vec<GroupEntry>,
+    written: usize,
+    unchanged: usize,
+}
+
+/// Writes `contents` unless the file already has them, so unchanged
+/// calendars keep their modification time. Returns whether the file was written.
+fn write_if_changed(path: &Path, contents: &[u8]) -> io::Result<bool> {
+    if fs::read(path).is_ok_and(|old| old == contents) {
+        return Ok(false);
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, contents)?;
+    Ok(true)
+}
+
+/// Subgroup names are used as file names.
+fn file_name(name: &str) -> String {
+    name.trim().replace(['/', '\\'], "_")
+}
+
+/// Writes calendars of a group as `{dep}/{form}/{group}.ics`, one
+/// `{dep}/{form}/{group}/{subgroup}+{subgroup}.ics` per combination of
+/// subgroups a student can pick, see `subgroup::combinations`, and with
+/// exams `exam/...` and `all/...` like the server routes. Subgroups of
+/// non-exclusive categories are in every combination. A static site has
+/// no query strings, so filters and other options are only served by
+/// the server.
+fn export_group(
+    cfg: &Config,
+    req: &Request,
+    schedule: Schedule,
+    exams: Option<ExamList>,
+    out: &Path,
+    progress: &Mutex<Progress>,
+) -> io::Result<()> {
+    let base = format!("{}/{}/{}", req.department, req.form, req.group);
+    let mut files = vec![(format!("{base}.ics"), schedule.to_ical(cfg, req))];
+
+    let mut entry = GroupEntry {
+        department: req.department.clone(),
+        department_name: schedule.student_group.department.full_name.clone(),
+        form: req.form.clone(),
+        group: req.group.clone(),
+        calendar: files[0].0.clone(),
+        combinations: BTreeMap::new(),
+        exams: None,
+        all: None,
+    };
+
+    let categories = subgroup::categories(&cfg.subgroups, &schedule);
+    let total: usize = categories
+        .iter()
+        .filter(|category| category.exclusive)
+        .map(|category| category.subgroups.len())
+        .product();
+    if total > MAX_COMBINATIONS {
+        log::warn!("Exporting {MAX_COMBINATIONS} of {total} subgroup combinations of {base}");
+    }
+    let common: Vec<String> = categories
+        .iter()
+        .filter(|category| !category.exclusive)
+        .flat_map(|category| category.subgroups.iter().cloned())
+        .collect();
+    for picked in subgroup::combinations(&categories, MAX_COMBINATIONS) {
+        let names: Vec<String> = picked.iter().map(|name| file_name(name)).collect();
+        let path = format!("{base}/{}.ics", names.join("+"));
+        let sub_req = Request {
+            subgroups: picked.iter().chain(&common).cloned().collect(),
+            ..req.clone()
+        };
+        entry.combinations.insert(picked.join(", "), path.clone());
+        files.push((path, schedule.to_ical(cfg, &sub_req)));
+    }
+
+    if let Some(exams) = exams {
+        let exam_path = format!("exam/{base}.ics");
+        files.push((exam_path.clone(), exams.to_ical(cfg, req)));
+        entry.exams = Some(exam_path);
+
+        let combined_req = Request {
+            exams: true,
+            ..req.clone()
+        };
+        let all_path = format!("all/{base}.ics");
+        let combined = Combined { schedule, exams };
+        files.push((all_path.clone(), combined.to_ical(cfg, &combined_req)));
+        entry.all = Some(all_path);
+    }
+
+    let mut written = 0;
+    for (path, calendar) in &files {
+        if write_if_changed(&out.join(path), calendar.to_string().as_bytes())? {
+            written += 1;
+        }
+    }
+
+    let mut progress = progress.lock().unwrap();
+    progress.written += written;
+    progress.unchanged += files.len() - written;
+    progress.entries.push(entry);
+    Ok(())
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Index page with links to every calendar, grouped by department.
+fn render_index(cfg: &Config, entries: &[GroupEntry]) -> String {
+    let title = escape_html(&cfg.app_name);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>{title}</title>\n</head>\n<body>\n<h1>{title}</h1>\n"
+    );
+
+    let mut department = None;
+    for entry in entries {
+        if department != Some(&entry.department) {
+            if department.is_some() {
+                html.push_str("</ul>\n");
+            }
+            department = Some(&entry.department);
+            html.push_str(&format!(
+                "<h2>{}</h2>\n<ul>\n",
+                escape_html(&entry.department_name)
+            ));
+        }
+
+        let mut links = vec![format!(
+            "<a href=\"{}\">{}</a>",
+            escape_html(&entry.calendar),
+            escape_html(&entry.group)
+        )];
+        for (picked, path) in &entry.combinations {
+            links.push(format!(
+                "<a href=\"{}\">{}</a>",
+                escape_html(path),
+                escape_html(picked)
+            ));
+        }
+        if let Some(path) = &entry.exams {
+            links.push(format!("<a href=\"{}\">сессия</a>", escape_html(path)));
+        }
+        if let Some(path) = &entry.all {
+            links.push(format!("<a href=\"{}\">всё</a>", escape_html(path)));
+        }
+        html.push_str(&format!(
+            "<li>{} ({}): {}</li>\n",
+            escape_html(&entry.group),
+            escape_html(&entry.form),
+            links.join(", ")
+        ));
+    }
+    if department.is_some() {
+        html.push_str("</ul>\n");
+    }
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// Writes the index page and the manifest of every exported group.
+fn write_site(cfg: &Config, out: &Path, mut entries: Vec<GroupEntry>) -> io::Result<usize> {
+    entries.sort_by(|a, b| {
+        (&a.department, &a.form, &a.group).cmp(&(&b.department, &b.form, &b.group))
+    });
+
+    let manifest = serde_json::to_vec_pretty(&entries)?;
+    let written = [
+        write_if_changed(&out.join("manifest.json"), &manifest)?,
+        write_if_changed(
+            &out.join("index.html"),
+            render_index(cfg, &entries).as_bytes(),
+        )?,
+    ];
+    Ok(written.into_iter().filter(|w| *w).count())
+}
+
+/// Exports calendars of every department and group into `out` as a static site.
+///
+/// Output only depends on the fetched data, so exporting twice gives the
+/// same files and only changed calendars are rewritten.
+pub async fn export(
+    cfg: &Config,
+    options: &CrawlOptions,
+    out: &Path,
+) -> RequestResult<ExportReport> {
+    let progress = Mutex::new(Progress::default());
+
+    let departments = crawl::walk(cfg, options, |req| {
+        let progress = &progress;
+        async move {
+            actix_web::rt::time::sleep(options.delay).await;
+            let schedule = tracto::fetch_schedule(cfg, &req).await?;
+
+            actix_web::rt::time::sleep(options.delay).await;
+            // Not every group has exams
+            let exams = tracto::fetch_exam(cfg, &req).await.ok();
+
+            export_group(cfg, &req, schedule, exams, out, progress)?;
+            Ok(())
+        }
+    })
+    .await?;
+
+    let progress = progress.into_inner().unwrap();
+    let site_written = write_site(cfg, out, progress.entries)?;
+    Ok(ExportReport {
+        departments,
+        written: progress.written + site_written,
+        unchanged: progress.unchanged + 2 - site_written,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::*;
+
+    #[test]
+    fn export_is_reproducible() {
+        let cfg = Config::default();
+        let out = std::env::temp_dir().join(format!("calar-export-{}", std::process::id()));
+        let req = Request {
+            department: "knt".to_string(),
+            form: "full".to_string(),
+            group: "351".to_string(),
+            ..Default::default()
+        };
+        let schedule = schedule(vec![
+            lesson_json(1, 1, "FULL", ""),
+            lesson_json(2, 2, "FULL", "1_под."),
+            lesson_json(3, 3, "FULL", "2_под."),
+            lesson_json(4, 4, "FULL", "анг.ст.1"),
+            lesson_json(5, 5, "FULL", "анг.ст.3"),
+            lesson_json(6, 6, "FULL", "цифровая_кафедра"),
+        ]);
+        let exams = exam_list(vec![exam_json(1, 2023, 6, 20)]);
+
+        let progress = Mutex::new(Progress::default());
+        export_group(
+            &cfg,
+            &req,
+            schedule.clone(),
+            Some(exams.clone()),
+            &out,
+            &progress,
+        )
+        .unwrap();
+        let first = progress.into_inner().unwrap();
+        assert_eq!(first.written, 7);
+
+        let progress = Mutex::new(Progress::default());
+        export_group(&cfg, &req, schedule, Some(exams), &out, &progress).unwrap();
+        let second = progress.into_inner().unwrap();
+        assert_eq!((second.written, second.unchanged), (0, 7));
+        assert_eq!(first.entries, second.entries);
+
+        let entry = &first.entries[0];
+        assert_eq!(entry.calendar, "knt/full/351.ics");
+        assert_eq!(entry.combinations.len(), 4);
+        assert_eq!(
+            entry.combinations["2_под., анг.ст.3"],
+            "knt/full/351/2_под.+анг.ст.3.ics"
+        );
+        assert_eq!(entry.all.as_deref(), Some("all/knt/full/351.ics"));
+
+        // Lessons of both categories, of the whole group and of
+        // non-exclusive subgroups, without the alternatives
+        let combination = fs::read_to_string(out.join("knt/full/351/2_под.+анг.ст.3.ics")).unwrap();
+        for id in [1, 3, 5, 6] {
+            assert!(combination.contains(&format!("UID:lesson-{id}@calar")));
+        }
+        for id in [2, 4] {
+            assert!(!combination.contains(&format!("UID:lesson-{id}@calar")));
+        }
+
+        assert_eq!(write_site(&cfg, &out, first.entries.clone()).unwrap(), 2);
+        assert_eq!(write_site(&cfg, &out, first.entries).unwrap(), 0);
+        let index = fs::read_to_string(out.join("index.html")).unwrap();
+        assert!(index.contains("<a href=\"exam/knt/full/351.ics\">"));
+
+        fs::remove_dir_all(out).unwrap();
+    }
+}
@@ -0,0 +1,191 @@
+use crate::{
+    diff::{Diff, DiffItem, ExamInfo, LessonInfo},
+    models::{ExamList, Schedule},
+    snapshot::Snapshot,
+    Config, Request,
+};
+
+use chrono::{DateTime, Utc};
+use std::fmt::Write;
+
+/// Maximum number of entries in a feed.
+const MAX_ENTRIES: usize = 50;
+
+struct Entry {
+    id: String,
+    title: &'static str,
+    updated: DateTime<Utc>,
+    content: String,
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Non-empty diffs between consecutive snapshots.
+fn entries<T, I>(
+    snapshots: &[Snapshot<T>],
+    items: impl Fn(&T) -> Vec<I>,
+    title: &'static str,
+    id_prefix: &str,
+) -> Vec<Entry>
+where
+    I: DiffItem + std::fmt::Display,
+{
+    snapshots
+        .windows(2)
+        .filter_map(|pair| {
+            let diff = Diff::from_items(&items(&pair[0].data), &items(&pair[1].data));
+            (!diff.is_empty()).then(|| Entry {
+                id: format!("{id_prefix}:{}", pair[1].taken_at.timestamp()),
+                title,
+                updated: pair[1].taken_at,
+                content: diff.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Atom feed of schedule and exam changes found in stored snapshots.
+/// Lessons are filtered by subgroups and translator flag of the request.
+pub fn atom(
+    cfg: &Config,
+    req: &Request,
+    schedules: &[Snapshot<Schedule>],
+    exams: &[Snapshot<ExamList>],
+) -> String {
+    let feed_id = format!("urn:calar:{}:{}:{}", req.department, req.form, req.group);
+
+    let lessons = |schedule: &Schedule| -> Vec<LessonInfo> {
+        schedule
+            .requested_lessons(cfg, req)
+            .map(LessonInfo::from)
+            .collect()
+    };
+    let exam_events = |exams: &ExamList| -> Vec<ExamInfo> {
+        exams
+            .exam_period_events
+            .iter()
+            .map(ExamInfo::from)
+            .collect()
+    };
+
+    let mut entries = entries(
+        schedules,
+        lessons,
+        "Изменения в расписании",
+        &format!("{feed_id}:schedule"),
+    );
+    entries.extend(self::entries(
+        exams,
+        exam_events,
+        "Изменения в расписании экзаменов",
+        &format!("{feed_id}:exams"),
+    ));
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.updated));
+    entries.truncate(MAX_ENTRIES);
+
+    let updated = entries
+        .first()
+        .map(|entry| entry.updated)
+        .or_else(|| schedules.last().map(|snapshot| snapshot.taken_at))
+        .unwrap_or_else(Utc::now);
+
+    let mut feed = String::new();
+    writeln!(feed, r#"<?xml version="1.0" encoding="utf-8"?>"#).unwrap();
+    writeln!(feed, r#"<feed xmlns="http://www.w3.org/2005/Atom">"#).unwrap();
+    writeln!(feed, "  <id>{}</id>", escape(&feed_id)).unwrap();
+    writeln!(
+        feed,
+        "  <title>{} {}: изменения</title>",
+        escape(&cfg.app_name),
+        escape(&req.group)
+    )
+    .unwrap();
+    writeln!(feed, "  <updated>{}</updated>", updated.to_rfc3339()).unwrap();
+    writeln!(
+        feed,
+        "  <author><name>{}</name></author>",
+        escape(&cfg.app_name)
+    )
+    .unwrap();
+    for entry in entries {
+        writeln!(feed, "  <entry>").unwrap();
+        writeln!(feed, "    <id>{}</id>", escape(&entry.id)).unwrap();
+        writeln!(feed, "    <title>{}</title>", entry.title).unwrap();
+        writeln!(
+            feed,
+            "    <updated>{}</updated>",
+            entry.updated.to_rfc3339()
+        )
+        .unwrap();
+        writeln!(
+            feed,
+            r#"    <content type="text">{}</content>"#,
+            escape(&entry.content)
+        )
+        .unwrap();
+        writeln!(feed, "  </entry>").unwrap();
+    }
+    writeln!(feed, "</feed>").unwrap();
+
+    feed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::*;
+    use chrono::TimeZone;
+
+    fn snapshot<T>(timestamp: i64, data: T) -> Snapshot<T> {
+        Snapshot {
+            taken_at: Utc.timestamp_opt(timestamp, 0).unwrap(),
+            data,
+        }
+    }
+
+    #[test]
+    fn entries_for_filtered_changes() {
+        let cfg = Config::default();
+        let mut moved = schedule(vec![
+            lesson_json(1, 1, "FULL", "1_под."),
+            lesson_json(2, 2, "FULL", "2_под."),
+        ]);
+        moved.lessons[0].place = String::from("9 корпус <101>");
+        let mut other_subgroup = moved.clone();
+        other_subgroup.lessons[1].place = String::from("8 корпус 1");
+        let schedules = vec![
+            snapshot(
+                1000,
+                schedule(vec![
+                    lesson_json(1, 1, "FULL", "1_под."),
+                    lesson_json(2, 2, "FULL", "2_под."),
+                ]),
+            ),
+            snapshot(2000, moved),
+            snapshot(3000, other_subgroup),
+        ];
+        let exams = vec![
+            snapshot(1500, exam_list(vec![])),
+            snapshot(2500, exam_list(vec![exam_json(1, 2023, 6, 10)])),
+        ];
+        let req = Request {
+            department: String::from("knt"),
+            form: String::from("full"),
+            group: String::from("351"),
+            subgroups: vec![String::from("1_под.")],
+            ..Default::default()
+        };
+
+        let feed = atom(&cfg, &req, &schedules, &exams);
+        assert_eq!(feed.matches("<entry>").count(), 2);
+        assert!(feed.contains("urn:calar:knt:full:351:schedule:2000"));
+        assert!(feed.contains("urn:calar:knt:full:351:exams:2500"));
+        assert!(feed.contains("9 корпус &lt;101&gt;"));
+        assert!(!feed.contains("8 корпус 1"));
+    }
+}
@@ -0,0 +1,220 @@
+use crate::{
+    models::{Lesson, WeekType},
+    Config,
+};
+
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// Lesson property a filter looks at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Field {
+    Name,
+    Teacher,
+    Type,
+    Place,
+    Weekday,
+    Week,
+}
+
+/// Lesson filter like `type:lecture` or `!name:физкультура`.
+///
+/// Lessons have to match at least one include filter of every field
+/// used in includes and none of the exclude filters. Text fields match
+/// a case-insensitive substring.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Filter {
+    pub exclude: bool,
+    pub field: Field,
+    pub value: String,
+}
+
+const WEEKDAYS: [[&str; 3]; 7] = [
+    ["mon", "пн", "понедельник"],
+    ["tue", "вт", "вторник"],
+    ["wed", "ср", "среда"],
+    ["thu", "чт", "четверг"],
+    ["fri", "пт", "пятница"],
+    ["sat", "сб", "суббота"],
+    ["sun", "вс", "воскресенье"],
+];
+
+impl Field {
+    fn as_str(self) -> &'static str {
+        match self {
+            Field::Name => "name",
+            Field::Teacher => "teacher",
+            Field::Type => "type",
+            Field::Place => "place",
+            Field::Weekday => "weekday",
+            Field::Week => "week",
+        }
+    }
+}
+
+/// Day number as in Tracto, 1 is Monday.
+fn weekday_number(value: &str) -> Option<u32> {
+    match value.parse::<u32>() {
+        Ok(n) if (1..=7).contains(&n) => Some(n),
+        Ok(_) => None,
+        Err(_) => WEEKDAYS
+            .iter()
+            .position(|names| names.contains(&value))
+            .map(|i| i as u32 + 1),
+    }
+}
+
+fn week_type(value: &str) -> Option<WeekType> {
+    match value {
+        "числитель" | "чис" => Some(WeekType::Nom),
+        "знаменатель" | "знам" => Some(WeekType::Denom),
+        "каждая" => Some(WeekType::Full),
+        _ => WeekType::from_known(value),
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (exclude, expr) = match s.strip_prefix(['!', '-']) {
+            Some(expr) => (true, expr),
+            None => (false, s),
+        };
+        let (field, value) = expr
+            .split_once(':')
+            .ok_or_else(|| format!("Missing field in filter {s:?}, use field:value"))?;
+        let field = match field.trim().to_lowercase().as_str() {
+            "name" => Field::Name,
+            "teacher" => Field::Teacher,
+            "type" => Field::Type,
+            "place" => Field::Place,
+            "weekday" | "day" => Field::Weekday,
+            "week" => Field::Week,
+            _ => {
+                return Err(format!(
+                    "Unknown field in filter {s:?}, use name, teacher, type, place, weekday or week"
+                ))
+            }
+        };
+        let value = value.trim().to_lowercase();
+        if value.is_empty() {
+            return Err(format!("Empty value in filter {s:?}"));
+        }
+        let known = match field {
+            Field::Weekday => weekday_number(&value).is_some(),
+            Field::Week => week_type(&value).is_some(),
+            _ => true,
+        };
+        if !known {
+            return Err(format!("Unknown {} in filter {s:?}", field.as_str()));
+        }
+
+        Ok(Self {
+            exclude,
+            field,
+            value,
+        })
+    }
+}
+
+impl TryFrom<String> for Filter {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Filter> for String {
+    fn from(value: Filter) -> Self {
+        value.to_string()
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.exclude {
+            write!(f, "!")?;
+        }
+        write!(f, "{}:{}", self.field.as_str(), self.value)
+    }
+}
+
+impl Filter {
+    pub fn matches(&self, cfg: &Config, lesson: &Lesson) -> bool {
+        let contains = |text: &str| text.to_lowercase().contains(&self.value);
+        match self.field {
+            Field::Name => contains(&lesson.name),
+            Field::Teacher => contains(&lesson.teacher.full()),
+            Field::Place => contains(&lesson.place),
+            Field::Type => {
+                let label = cfg.lesson_type_label(&lesson.lesson_type);
+                [lesson.lesson_type.as_str(), &label.short, &label.full]
+                    .iter()
+                    .any(|name| name.to_lowercase() == self.value)
+            }
+            Field::Weekday => weekday_number(&self.value) == Some(lesson.day.day_number),
+            Field::Week => week_type(&self.value).as_ref() == Some(&lesson.week_type),
+        }
+    }
+}
+
+/// Whether the lesson passes all the filters.
+pub fn passes(filters: &[Filter], cfg: &Config, lesson: &Lesson) -> bool {
+    let (excludes, includes): (Vec<_>, Vec<_>) = filters.iter().partition(|f| f.exclude);
+    if excludes.iter().any(|f| f.matches(cfg, lesson)) {
+        return false;
+    }
+
+    includes.iter().all(|include| {
+        includes
+            .iter()
+            .filter(|f| f.field == include.field)
+            .any(|f| f.matches(cfg, lesson))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::*;
+
+    fn filters(exprs: &[&str]) -> Vec<Filter> {
+        exprs.iter().map(|expr| expr.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn filters_include_and_exclude() {
+        let cfg = Config::default();
+        let mut practice = lesson_json(2, 3, "NOM", "");
+        practice["lessonType"] = "PRACTICE".into();
+        practice["name"] = "Физкультура".into();
+        let schedule = schedule(vec![lesson_json(1, 1, "FULL", ""), practice]);
+        let (lecture, practice) = (&schedule.lessons[0], &schedule.lessons[1]);
+
+        let lectures = filters(&["type:Лекция"]);
+        assert!(passes(&lectures, &cfg, lecture));
+        assert!(!passes(&lectures, &cfg, practice));
+
+        let no_sport = filters(&["!name:ФИЗКУЛЬТ"]);
+        assert!(passes(&no_sport, &cfg, lecture));
+        assert!(!passes(&no_sport, &cfg, practice));
+
+        let days = filters(&["weekday:пн", "day:3", "week:числитель"]);
+        assert!(!passes(&days, &cfg, lecture));
+        assert!(passes(&days, &cfg, practice));
+
+        assert!(passes(&filters(&["type:l", "type:П"]), &cfg, practice));
+
+        assert_eq!(
+            filters(&["-Place: 12 Корпус"])[0].to_string(),
+            "!place:12 корпус"
+        );
+        assert!("weekday:8".parse::<Filter>().is_err());
+        assert!("room:414".parse::<Filter>().is_err());
+        assert!("lecture".parse::<Filter>().is_err());
+    }
+}
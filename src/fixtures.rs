@@ -0,0 +1,106 @@
+//! Tracto-shaped test data.
+
+use crate::models::*;
+
+pub fn lesson_json(id: u32, day: u32, week_type: &str, sub_group: &str) -> serde_json::Value {
+    serde_json::json!({
+        "id": id,
+        "name": format!("Предмет {id}"),
+        "place": "12 корпус 414",
+        "department": department_json(),
+        "studentGroup": group_json(),
+        "subGroup": sub_group,
+        "day": { "id": null, "dayNumber": day, "weekDay": null },
+        "lessonTime": {
+            "id": 1,
+            "lessonNumber": 1,
+            "hourStart": 8,
+            "minuteStart": 20,
+            "hourEnd": 9,
+            "minuteEnd": 50
+        },
+        "teacher": teacher_json(),
+        "weekType": week_type,
+        "lessonType": "LECTURE",
+        "updatedTimestamp": 0,
+        "beginTimestamp": null,
+        "endTimestamp": null
+    })
+}
+
+pub fn exam_json(id: u32, year: i32, month: u32, day: u32) -> serde_json::Value {
+    typed_exam_json(id, "EXAM", year, month, day)
+}
+
+pub fn typed_exam_json(
+    id: u32,
+    exam_type: &str,
+    year: i32,
+    month: u32,
+    day: u32,
+) -> serde_json::Value {
+    serde_json::json!({
+        "id": id,
+        "examPeriodEventType": exam_type,
+        "day": day,
+        "month": {
+            "number": month,
+            "rusNominative": "",
+            "rusGenitive": "",
+            "eng": ""
+        },
+        "year": format!("{year} г."),
+        "hour": 10,
+        "minute": 0,
+        "subjectName": format!("Экзамен {id}"),
+        "teacher": teacher_json(),
+        "studentGroup": group_json(),
+        "place": "12 корпус 414"
+    })
+}
+
+fn department_json() -> serde_json::Value {
+    serde_json::json!({
+        "id": 1,
+        "fullName": "Факультет КНиИТ",
+        "shortName": "КНиИТ",
+        "url": "knt"
+    })
+}
+
+fn group_json() -> serde_json::Value {
+    serde_json::json!({
+        "id": 1,
+        "groupNumber": "351",
+        "groupNumberRus": "351",
+        "department": department_json(),
+        "educationForm": "FULL",
+        "groupType": "BACHELOR"
+    })
+}
+
+fn teacher_json() -> serde_json::Value {
+    serde_json::json!({
+        "id": 1,
+        "surname": "Иванов",
+        "name": "Иван",
+        "patronymic": "Иванович"
+    })
+}
+
+pub fn schedule(lessons: Vec<serde_json::Value>) -> Schedule {
+    serde_json::from_value(serde_json::json!({
+        "lessons": lessons,
+        "studentGroup": group_json(),
+        "day": { "id": null, "dayNumber": 1, "weekDay": null }
+    }))
+    .unwrap()
+}
+
+pub fn exam_list(exams: Vec<serde_json::Value>) -> ExamList {
+    serde_json::from_value(serde_json::json!({
+        "examPeriodEvents": exams,
+        "studentGroup": group_json()
+    }))
+    .unwrap()
+}
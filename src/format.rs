@@ -0,0 +1,623 @@
+use crate::{
+    calendar::Combined,
+    models::{ExamEvent, ExamList, Lesson, Schedule, WeekType},
+    occurrence::Occurrence,
+    Config, Request,
+};
+
+use icalendar::{Calendar, CalendarComponent, Component, Property};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+
+/// Representation of the requested calendar.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// iCalendar (RFC 5545)
+    #[default]
+    Ical,
+    /// jCal, iCalendar as JSON (RFC 7265)
+    Jcal,
+    /// xCal, iCalendar as XML (RFC 6321)
+    Xcal,
+    /// One row per occurrence
+    Csv,
+    /// List of occurrences
+    Json,
+    /// Weekly table
+    Markdown,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Ical => "ics",
+            Format::Jcal => "jcal",
+            Format::Xcal => "xcs",
+            Format::Csv => "csv",
+            Format::Json => "json",
+            Format::Markdown => "md",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Ical => "text/calendar; charset=utf-8",
+            Format::Jcal => "application/calendar+json",
+            Format::Xcal => "application/calendar+xml",
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::Json => "application/json",
+            Format::Markdown => "text/markdown; charset=utf-8",
+        }
+    }
+
+    /// Preferred supported format of an `Accept` header.
+    ///
+    /// Only explicit calendar and data media types count. Wildcards and
+    /// `text/plain` are ignored, so browsers and calendar clients get the default.
+    pub fn from_accept(accept: &str) -> Option<Self> {
+        let mut ranges: Vec<(f32, &str)> = accept
+            .split(',')
+            .map(|range| {
+                let mut parts = range.split(';').map(str::trim);
+                let media_type = parts.next().unwrap_or_default();
+                let quality = parts
+                    .find_map(|param| param.strip_prefix("q="))
+                    .and_then(|q| q.parse().ok())
+                    .unwrap_or(1.0);
+                (quality, media_type)
+            })
+            .filter(|(quality, _)| *quality > 0.0)
+            .collect();
+        // Stable sort keeps the header order among equal qualities
+        ranges.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        ranges
+            .into_iter()
+            .find_map(|(_, media_type)| match media_type.to_lowercase().as_str() {
+                "text/calendar" => Some(Format::Ical),
+                "application/calendar+json" => Some(Format::Jcal),
+                "application/calendar+xml" => Some(Format::Xcal),
+                "text/csv" => Some(Format::Csv),
+                "application/json" => Some(Format::Json),
+                "text/markdown" => Some(Format::Markdown),
+                _ => None,
+            })
+    }
+}
+
+/// Data a calendar is made of.
+pub trait Source {
+    fn calendar(&self, cfg: &Config, request: &Request) -> Calendar;
+    fn occurrences(&self, cfg: &Config, request: &Request) -> Vec<Occurrence>;
+    fn lessons(&self) -> &[Lesson];
+    fn exams(&self) -> &[ExamEvent];
+}
+
+impl Source for Schedule {
+    fn calendar(&self, cfg: &Config, request: &Request) -> Calendar {
+        self.to_ical(cfg, request)
+    }
+
+    fn occurrences(&self, cfg: &Config, request: &Request) -> Vec<Occurrence> {
+        self.occurrences(cfg, request)
+    }
+
+    fn lessons(&self) -> &[Lesson] {
+        &self.lessons
+    }
+
+    fn exams(&self) -> &[ExamEvent] {
+        &[]
+    }
+}
+
+impl Source for ExamList {
+    fn calendar(&self, cfg: &Config, request: &Request) -> Calendar {
+        self.to_ical(cfg, request)
+    }
+
+    fn occurrences(&self, cfg: &Config, request: &Request) -> Vec<Occurrence> {
+        self.occurrences(cfg, request)
+    }
+
+    fn lessons(&self) -> &[Lesson] {
+        &[]
+    }
+
+    fn exams(&self) -> &[ExamEvent] {
+        &self.exam_period_events
+    }
+}
+
+impl Source for Combined {
+    fn calendar(&self, cfg: &Config, request: &Request) -> Calendar {
+        self.to_ical(cfg, request)
+    }
+
+    fn occurrences(&self, cfg: &Config, request: &Request) -> Vec<Occurrence> {
+        self.occurrences(cfg, request)
+    }
+
+    fn lessons(&self) -> &[Lesson] {
+        &self.schedule.lessons
+    }
+
+    fn exams(&self) -> &[ExamEvent] {
+        &self.exams.exam_period_events
+    }
+}
+
+/// Renders `source` in the format of the request.
+pub fn render<T: Source>(source: &T, cfg: &Config, request: &Request) -> String {
+    match request.format {
+        Format::Ical => source.calendar(cfg, request).to_string(),
+        Format::Jcal => jcal(&Node::from(&source.calendar(cfg, request))).to_string(),
+        Format::Xcal => xcal(&Node::from(&source.calendar(cfg, request))),
+        Format::Csv => csv(&source.occurrences(cfg, request)),
+        Format::Json => serde_json::to_string(&source.occurrences(cfg, request)).unwrap(),
+        Format::Markdown => markdown(source, cfg, request),
+    }
+}
+
+/// Component of a calendar with properties in a stable order.
+struct Node {
+    name: String,
+    properties: Vec<Prop>,
+    children: Vec<Node>,
+}
+
+struct Prop {
+    name: String,
+    params: BTreeMap<String, String>,
+    value_type: &'static str,
+    value: String,
+}
+
+impl From<&Property> for Prop {
+    fn from(property: &Property) -> Self {
+        let value_type = match property.key() {
+            "DTSTAMP" | "DTSTART" | "DTEND" | "EXDATE" if property.value().contains('T') => {
+                "date-time"
+            }
+            "DTSTAMP" | "DTSTART" | "DTEND" | "EXDATE" => "date",
+            "RRULE" => "recur",
+            "TRIGGER" => "duration",
+            _ => "text",
+        };
+        Self {
+            name: property.key().to_lowercase(),
+            params: property
+                .params()
+                .values()
+                .map(|param| (param.key().to_lowercase(), param.value().to_string()))
+                .collect(),
+            value_type,
+            value: property.value().replace("\\n", "\n"),
+        }
+    }
+}
+
+impl Node {
+    fn new<C: Component>(component: &C) -> Self {
+        Self {
+            name: component.component_kind().to_lowercase(),
+            properties: component
+                .properties()
+                .values()
+                .chain(component.multi_properties())
+                .map(Prop::from)
+                .collect(),
+            children: component.components().iter().map(Node::new).collect(),
+        }
+    }
+}
+
+impl From<&Calendar> for Node {
+    fn from(calendar: &Calendar) -> Self {
+        Self {
+            name: String::from("vcalendar"),
+            properties: calendar.properties.iter().map(Prop::from).collect(),
+            children: calendar
+                .iter()
+                .filter_map(|component| match component {
+                    CalendarComponent::Event(event) => Some(Node::new(event)),
+                    CalendarComponent::Todo(todo) => Some(Node::new(todo)),
+                    CalendarComponent::Venue(venue) => Some(Node::new(venue)),
+                    _ => None,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// `20230206T082000Z` to `2023-02-06T08:20:00Z`.
+fn iso_date_time(value: &str) -> String {
+    let mut iso = String::new();
+    for (i, c) in value.chars().enumerate() {
+        match i {
+            4 | 6 => iso.push('-'),
+            11 | 13 => iso.push(':'),
+            _ => {}
+        }
+        iso.push(c);
+    }
+    iso
+}
+
+/// Dates of a property, EXDATE may list several separated by commas.
+fn iso_dates(value: &str) -> impl Iterator<Item = String> + '_ {
+    value.split(',').map(iso_date_time)
+}
+
+/// Parts of a recurrence rule, e.g. `FREQ=WEEKLY;INTERVAL=2`.
+fn recur_parts(value: &str) -> Vec<(String, String)> {
+    value
+        .split(';')
+        .filter_map(|part| part.split_once('='))
+        .map(|(key, value)| {
+            let value = match key {
+                "UNTIL" => iso_date_time(value),
+                _ => value.to_string(),
+            };
+            (key.to_lowercase(), value)
+        })
+        .collect()
+}
+
+fn jcal(node: &Node) -> Value {
+    let properties: Vec<Value> = node
+        .properties
+        .iter()
+        .map(|prop| {
+            let mut values = vec![json!(prop.name), json!(prop.params), json!(prop.value_type)];
+            match prop.value_type {
+                "date-time" | "date" => values.extend(iso_dates(&prop.value).map(Value::from)),
+                "recur" => values.push(
+                    recur_parts(&prop.value)
+                        .into_iter()
+                        .map(|(key, value)| match value.parse::<u64>() {
+                            Ok(number) if key != "until" => (key, Value::from(number)),
+                            _ => (key, Value::from(value)),
+                        })
+                        .collect::<serde_json::Map<_, _>>()
+                        .into(),
+                ),
+                _ => values.push(Value::from(prop.value.as_str())),
+            }
+            Value::from(values)
+        })
+        .collect();
+    let children: Vec<Value> = node.children.iter().map(jcal).collect();
+    json!([node.name, properties, children])
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn xcal_node(xml: &mut String, node: &Node, indent: usize) {
+    let pad = " ".repeat(indent);
+    writeln!(xml, "{pad}<{}>", node.name).unwrap();
+    writeln!(xml, "{pad}  <properties>").unwrap();
+    for prop in &node.properties {
+        write!(xml, "{pad}    <{}>", prop.name).unwrap();
+        if !prop.params.is_empty() {
+            xml.push_str("<parameters>");
+            for (name, value) in &prop.params {
+                write!(xml, "<{name}><text>{}</text></{name}>", escape_xml(value)).unwrap();
+            }
+            xml.push_str("</parameters>");
+        }
+        let values: Vec<String> = match prop.value_type {
+            "date-time" | "date" => iso_dates(&prop.value).map(|v| escape_xml(&v)).collect(),
+            "recur" => vec![recur_parts(&prop.value)
+                .into_iter()
+                .map(|(key, value)| format!("<{key}>{}</{key}>", escape_xml(&value)))
+                .collect()],
+            _ => vec![escape_xml(&prop.value)],
+        };
+        for value in values {
+            write!(xml, "<{0}>{value}</{0}>", prop.value_type).unwrap();
+        }
+        writeln!(xml, "</{}>", prop.name).unwrap();
+    }
+    writeln!(xml, "{pad}  </properties>").unwrap();
+    if !node.children.is_empty() {
+        writeln!(xml, "{pad}  <components>").unwrap();
+        for child in &node.children {
+            xcal_node(xml, child, indent + 4);
+        }
+        writeln!(xml, "{pad}  </components>").unwrap();
+    }
+    writeln!(xml, "{pad}</{}>", node.name).unwrap();
+}
+
+fn xcal(node: &Node) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<icalendar xmlns=\"urn:ietf:params:xml:ns:icalendar-2.0\">\n");
+    xcal_node(&mut xml, node, 2);
+    xml.push_str("</icalendar>\n");
+    xml
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn csv(occurrences: &[Occurrence]) -> String {
+    let mut csv = String::from("date,start,end,kind,summary,description,location,category,uid\r\n");
+    for occurrence in occurrences {
+        let kind = serde_json::to_value(occurrence.kind).unwrap();
+        let fields = [
+            occurrence.start.format("%Y-%m-%d").to_string(),
+            occurrence.start.format("%H:%M").to_string(),
+            occurrence.end.format("%H:%M").to_string(),
+            kind.as_str().unwrap_or_default().to_string(),
+            occurrence.summary.clone(),
+            occurrence.description.clone(),
+            occurrence.location.clone(),
+            occurrence.category.clone(),
+            occurrence.uid.clone(),
+        ];
+        let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+const WEEKDAYS: [&str; 7] = ["Пн", "Вт", "Ср", "Чт", "Пт", "Сб", "Вс"];
+
+fn escape_cell(s: &str) -> String {
+    s.replace('|', "\\|").replace('\n', "<br>")
+}
+
+/// Weekly table of lessons followed by the list of exams.
+fn markdown<T: Source>(source: &T, cfg: &Config, request: &Request) -> String {
+    let mut md = String::new();
+
+    let lessons: Vec<&Lesson> = source
+        .lessons()
+        .iter()
+        .filter(|lesson| lesson.is_requested(cfg, request))
+        .collect();
+    if !lessons.is_empty() {
+        let days = lessons
+            .iter()
+            .map(|lesson| lesson.day.day_number as usize)
+            .max()
+            .unwrap_or_default()
+            .clamp(6, WEEKDAYS.len());
+        let times: BTreeSet<_> = lessons
+            .iter()
+            .map(|lesson| {
+                let time = &lesson.lesson_time;
+                (
+                    time.lesson_number,
+                    format!(
+                        "{:02}:{:02}–{:02}:{:02}",
+                        time.hour_start, time.minute_start, time.hour_end, time.minute_end
+                    ),
+                )
+            })
+            .collect();
+
+        writeln!(md, "| Пара | {} |", WEEKDAYS[..days].join(" | ")).unwrap();
+        writeln!(md, "|---|{}", "---|".repeat(days)).unwrap();
+        for (number, time) in &times {
+            let mut row = vec![format!("{number} ({time})")];
+            for day in 1..=days {
+                let cell: Vec<String> = lessons
+                    .iter()
+                    .filter(|lesson| {
+                        lesson.day.day_number as usize == day
+                            && lesson.lesson_time.lesson_number == *number
+                    })
+                    .map(|lesson| {
+                        let text = lesson.text(cfg, request);
+                        let mut cell = text.summary;
+                        match lesson.week_type {
+                            WeekType::Nom => cell.push_str(" [чис.]"),
+                            WeekType::Denom => cell.push_str(" [знам.]"),
+                            _ => {}
+                        }
+                        if !lesson.sub_group.trim().is_empty() {
+                            write!(cell, " [{}]", lesson.sub_group.trim()).unwrap();
+                        }
+                        if !text.location.is_empty() {
+                            write!(cell, ", {}", text.location).unwrap();
+                        }
+                        escape_cell(&cell)
+                    })
+                    .collect();
+                row.push(cell.join("<br>"));
+            }
+            writeln!(md, "| {} |", row.join(" | ")).unwrap();
+        }
+    }
+
+    let mut exams: Vec<_> = source
+        .exams()
+        .iter()
+        .filter_map(|exam| exam.occurrence(cfg, request))
+        .collect();
+    exams.sort_by_key(|exam| exam.start);
+    if !exams.is_empty() {
+        if !md.is_empty() {
+            md.push('\n');
+        }
+        md.push_str("| Дата | Время | Событие | Место |\n|---|---|---|---|\n");
+        for exam in exams {
+            writeln!(
+                md,
+                "| {} | {} | {} | {} |",
+                exam.start.format("%d.%m.%Y"),
+                exam.start.format("%H:%M"),
+                escape_cell(&exam.summary),
+                escape_cell(&exam.location)
+            )
+            .unwrap();
+        }
+    }
+
+    md
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures::*, Holiday};
+    use chrono::{Datelike, Duration};
+
+    fn combined() -> Combined {
+        Combined {
+            schedule: schedule(vec![
+                lesson_json(1, 1, "FULL", ""),
+                lesson_json(2, 1, "NOM", "1_под."),
+            ]),
+            exams: exam_list(vec![exam_json(1, 2023, 6, 20)]),
+        }
+    }
+
+    fn rendered(format: Format) -> String {
+        let request = Request {
+            exams: true,
+            format,
+            alarm: vec!["15m".parse().unwrap()],
+            ..Default::default()
+        };
+        render(&combined(), &Config::default(), &request)
+    }
+
+    #[test]
+    fn accept_header() {
+        assert_eq!(
+            Format::from_accept("application/calendar+json"),
+            Some(Format::Jcal)
+        );
+        assert_eq!(
+            Format::from_accept("text/csv;q=0.5, application/json"),
+            Some(Format::Json)
+        );
+        assert_eq!(Format::from_accept("text/html,*/*;q=0.8"), None);
+        assert_eq!(Format::from_accept("text/plain"), None);
+    }
+
+    #[test]
+    fn jcal_follows_ical() {
+        let jcal: Value = serde_json::from_str(&rendered(Format::Jcal)).unwrap();
+        assert_eq!(jcal[0], "vcalendar");
+        let events = jcal[2].as_array().unwrap();
+        assert_eq!(events.len(), 3);
+
+        let lesson = &events[0];
+        assert_eq!(lesson[0], "vevent");
+        let prop = |name: &str| {
+            lesson[1]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|prop| prop[0] == name)
+                .unwrap()
+                .clone()
+        };
+        assert_eq!(prop("uid")[3], "lesson-1@calar");
+        assert_eq!(prop("dtstart")[1]["tzid"], "Europe/Saratov");
+        assert_eq!(prop("dtstart")[2], "date-time");
+        assert_eq!(prop("rrule")[3]["freq"], "WEEKLY");
+        assert_eq!(prop("rrule")[3]["interval"], 1);
+        assert_eq!(lesson[2][0][0], "valarm");
+    }
+
+    #[test]
+    fn xcal_follows_ical() {
+        let xcal = rendered(Format::Xcal);
+        assert!(xcal.contains("<icalendar xmlns=\"urn:ietf:params:xml:ns:icalendar-2.0\">"));
+        assert!(xcal.contains("<uid><text>exam-1@calar</text></uid>"));
+        assert!(xcal.contains(
+            "<dtstart><parameters><tzid><text>Europe/Saratov</text></tzid></parameters>\
+             <date-time>2023-06-20T10:00:00</date-time></dtstart>"
+        ));
+        assert!(xcal.contains("<recur><freq>WEEKLY</freq><interval>2</interval>"));
+        assert_eq!(xcal.matches("<valarm>").count(), 2);
+    }
+
+    #[test]
+    fn exdates_are_separate_values() {
+        let mut cfg = Config::default();
+        let schedule = schedule(vec![lesson_json(1, 1, "FULL", "")]);
+        // Two weeks off, so two Mondays are excluded
+        let (monday, _) = schedule.lessons[0].first_span(&cfg);
+        let first = monday.date_naive() + Duration::weeks(2);
+        let last = first + Duration::days(13);
+        cfg.semester.holidays = vec![Holiday {
+            name: "Каникулы".to_string(),
+            start_md: (first.month(), first.day()),
+            end_md: Some((last.month(), last.day())),
+        }];
+        let exdates = [first, first + Duration::weeks(1)]
+            .map(|day| format!("{}T08:20:00", day.format("%Y-%m-%d")));
+
+        let request = Request {
+            format: Format::Jcal,
+            ..Default::default()
+        };
+        let jcal: Value = serde_json::from_str(&render(&schedule, &cfg, &request)).unwrap();
+        let exdate = jcal[2][0][1]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|prop| prop[0] == "exdate")
+            .unwrap()
+            .clone();
+        assert_eq!(
+            exdate,
+            json!(["exdate", {"tzid": "Europe/Saratov"}, "date-time", exdates[0], exdates[1]])
+        );
+
+        let request = Request {
+            format: Format::Xcal,
+            ..Default::default()
+        };
+        let xcal = render(&schedule, &cfg, &request);
+        assert!(xcal.contains(&format!(
+            "<exdate><parameters><tzid><text>Europe/Saratov</text></tzid></parameters>\
+             <date-time>{}</date-time><date-time>{}</date-time></exdate>",
+            exdates[0], exdates[1]
+        )));
+    }
+
+    #[test]
+    fn csv_and_json_list_occurrences() {
+        let csv = rendered(Format::Csv);
+        let json: Vec<Value> = serde_json::from_str(&rendered(Format::Json)).unwrap();
+        assert_eq!(csv.lines().count(), json.len() + 1);
+        assert!(csv.contains("2023-06-20,10:00,14:00,exam,Экзамен 1 (Экзамен)"));
+        assert!(json
+            .iter()
+            .any(|o| o["start"] == "2023-06-20T10:00:00+04:00"));
+    }
+
+    #[test]
+    fn markdown_table() {
+        let md = rendered(Format::Markdown);
+        assert!(md.starts_with("| Пара | Пн | Вт | Ср | Чт | Пт | Сб |\n"));
+        assert!(md.contains(
+            "| 1 (08:20–09:50) | Предмет 1 (Л), 12 корпус 414<br>\
+             Предмет 2 (Л) [чис.] [1_под.], 12 корпус 414 |"
+        ));
+        assert!(md.contains("| 20.06.2023 | 10:00 | Экзамен 1 (Экзамен) | 12 корпус 414 |"));
+    }
+}
@@ -0,0 +1,389 @@
+use crate::{
+    bells::grid,
+    db,
+    expand::Term,
+    merge::{same_lesson, GroupRef},
+    models::{Lesson, Schedule},
+    occurrence::rfc3339,
+    show::WEEKDAYS,
+    snapshot,
+    tracto::{self, RequestError, RequestResult},
+    Config, Request,
+};
+
+use chrono::{prelude::*, Duration};
+use chrono_tz::{Europe::Saratov, Tz};
+use clap::Parser;
+use serde::Serialize;
+use std::fmt::Write;
+
+/// Longest range of a query in days.
+pub const MAX_DAYS: i64 = 62;
+
+/// Most groups and teachers of a query, every group is fetched from Tracto.
+pub const MAX_ATTENDEES: usize = 10;
+
+#[derive(Parser, Debug)]
+pub struct FreeArgs {
+    /// Groups, e.g. knt/full/351 or knt/full/351:1_под.
+    #[arg(short, long)]
+    pub groups: Vec<GroupRef>,
+    /// Teacher ids, known from crawled schedules
+    #[arg(short, long)]
+    pub teachers: Vec<u32>,
+    /// First day, today by default
+    #[arg(long)]
+    pub from: Option<NaiveDate>,
+    /// Last day, a week after the first one by default
+    #[arg(long)]
+    pub to: Option<NaiveDate>,
+    /// Print VFREEBUSY instead of a table
+    #[arg(long)]
+    pub ical: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Query {
+    pub groups: Vec<GroupRef>,
+    pub teachers: Vec<u32>,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+}
+
+impl Query {
+    pub fn new(
+        groups: Vec<GroupRef>,
+        teachers: Vec<u32>,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+        today: NaiveDate,
+    ) -> Result<Self, String> {
+        if groups.is_empty() && teachers.is_empty() {
+            return Err("Give at least one group or teacher".into());
+        }
+        if groups.len() + teachers.len() > MAX_ATTENDEES {
+            return Err(format!("Give at most {MAX_ATTENDEES} groups and teachers"));
+        }
+        let from = from.unwrap_or(today);
+        let to = to.unwrap_or(from + Duration::days(6));
+        if to < from {
+            return Err("Range ends before it starts".into());
+        }
+        if (to - from).num_days() >= MAX_DAYS {
+            return Err(format!("Range is longer than {MAX_DAYS} days"));
+        }
+        Ok(Self {
+            groups,
+            teachers,
+            from,
+            to,
+        })
+    }
+}
+
+/// Group or teacher with the lessons keeping them busy.
+pub struct Attendee {
+    pub who: String,
+    pub request: Request,
+    pub lessons: Vec<Lesson>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Busy {
+    pub who: String,
+    pub summary: String,
+    #[serde(serialize_with = "rfc3339")]
+    pub start: DateTime<Tz>,
+    #[serde(serialize_with = "rfc3339")]
+    pub end: DateTime<Tz>,
+}
+
+/// Pair everyone is free at.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Slot {
+    pub lesson_number: u8,
+    #[serde(serialize_with = "rfc3339")]
+    pub start: DateTime<Tz>,
+    #[serde(serialize_with = "rfc3339")]
+    pub end: DateTime<Tz>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FreeBusy {
+    pub busy: Vec<Busy>,
+    pub free: Vec<Slot>,
+}
+
+async fn group_attendee(cfg: &Config, group: &GroupRef) -> RequestResult<Attendee> {
+    let mut request = group.request(&Request::default());
+    let schedule = tracto::validate_request(cfg, &mut request).await?.schedule;
+    let lessons = schedule.requested_lessons(cfg, &request).cloned().collect();
+    Ok(Attendee {
+        who: group.to_string(),
+        request,
+        lessons,
+    })
+}
+
+/// Lessons of a teacher, collected from the latest snapshots of
+/// the groups the database knows they teach.
+fn teacher_attendee(teacher_id: u32) -> RequestResult<Attendee> {
+    let conn = db::open().map_err(|e| RequestError::Io(e.to_string()))?;
+    let groups =
+        db::teacher_groups(&conn, teacher_id).map_err(|e| RequestError::Io(e.to_string()))?;
+    if groups.is_empty() {
+        return Err(RequestError::Invalid(format!(
+            "No lessons of teacher {teacher_id} are known, crawl their groups first"
+        )));
+    }
+
+    let mut lessons: Vec<Lesson> = Vec::new();
+    for (department, form, group) in groups {
+        let request = Request {
+            department,
+            form,
+            group,
+            ..Default::default()
+        };
+        let Some(snapshot) = snapshot::load_latest::<Schedule>(&request)? else {
+            continue;
+        };
+        for lesson in snapshot.data.lessons {
+            if lesson.teacher.id == teacher_id && !lessons.iter().any(|l| same_lesson(l, &lesson)) {
+                lessons.push(lesson);
+            }
+        }
+    }
+
+    Ok(Attendee {
+        who: lessons
+            .first()
+            .map(|lesson| lesson.teacher.full())
+            .unwrap_or_else(|| format!("teacher {teacher_id}")),
+        request: Request {
+            translator: true,
+            ..Default::default()
+        },
+        lessons,
+    })
+}
+
+pub async fn attendees(cfg: &Config, query: &Query) -> RequestResult<Vec<Attendee>> {
+    let mut attendees = Vec::new();
+    for group in &query.groups {
+        attendees.push(group_attendee(cfg, group).await?);
+    }
+    for &teacher_id in &query.teachers {
+        attendees.push(teacher_attendee(teacher_id)?);
+    }
+    Ok(attendees)
+}
+
+/// Busy time of everyone from `from` to `to` inclusive, and the pairs
+/// all of them are free at. Sundays have no pairs.
+pub fn free_busy(cfg: &Config, attendees: &[Attendee], from: NaiveDate, to: NaiveDate) -> FreeBusy {
+    let range = from..=to;
+    let term = Term::new(cfg).between(from, to);
+    let mut busy = Vec::new();
+    for attendee in attendees {
+        for lesson in &attendee.lessons {
+            for occurrence in lesson.occurrences(cfg, &attendee.request, &term) {
+                busy.push(Busy {
+                    who: attendee.who.clone(),
+                    summary: occurrence.summary,
+                    start: occurrence.start,
+                    end: occurrence.end,
+                });
+            }
+        }
+    }
+    busy.sort_by(|a, b| (a.start, &a.who).cmp(&(b.start, &b.who)));
+
+    let grid = grid(attendees.iter().flat_map(|a| &a.lessons));
+    let mut free = Vec::new();
+    for date in from.iter_days().take_while(|date| range.contains(date)) {
+        if date.weekday() == Weekday::Sun {
+            continue;
+        }
+        for (&lesson_number, &(start, end)) in &grid {
+            let (Some(start), Some(end)) = (
+                Saratov.from_local_datetime(&date.and_time(start)).single(),
+                Saratov.from_local_datetime(&date.and_time(end)).single(),
+            ) else {
+                continue;
+            };
+            if !busy.iter().any(|b| b.start < end && start < b.end) {
+                free.push(Slot {
+                    lesson_number,
+                    start,
+                    end,
+                });
+            }
+        }
+    }
+
+    FreeBusy { busy, free }
+}
+
+fn utc(moment: DateTime<Tz>) -> String {
+    moment
+        .with_timezone(&Utc)
+        .format("%Y%m%dT%H%M%SZ")
+        .to_string()
+}
+
+/// VFREEBUSY component listing busy and free periods of the range.
+pub fn vfreebusy(
+    free_busy: &FreeBusy,
+    from: NaiveDate,
+    to: NaiveDate,
+    stamp: DateTime<Utc>,
+) -> String {
+    let midnight = |date: NaiveDate| {
+        Saratov
+            .from_local_datetime(&date.and_time(NaiveTime::MIN))
+            .unwrap()
+    };
+
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//calar//freebusy//RU".to_string(),
+        "BEGIN:VFREEBUSY".to_string(),
+        format!("DTSTAMP:{}", stamp.format("%Y%m%dT%H%M%SZ")),
+        format!("DTSTART:{}", utc(midnight(from))),
+        format!("DTEND:{}", utc(midnight(to + Duration::days(1)))),
+    ];
+
+    // Overlapping lessons of different attendees make one period
+    let mut periods: Vec<(DateTime<Tz>, DateTime<Tz>)> = Vec::new();
+    for busy in &free_busy.busy {
+        match periods.last_mut() {
+            Some((_, end)) if busy.start <= *end => *end = (*end).max(busy.end),
+            _ => periods.push((busy.start, busy.end)),
+        }
+    }
+    for (start, end) in periods {
+        lines.push(format!("FREEBUSY;FBTYPE=BUSY:{}/{}", utc(start), utc(end)));
+    }
+    for slot in &free_busy.free {
+        lines.push(format!(
+            "FREEBUSY;FBTYPE=FREE:{}/{}",
+            utc(slot.start),
+            utc(slot.end)
+        ));
+    }
+
+    lines.push("END:VFREEBUSY".to_string());
+    lines.push("END:VCALENDAR".to_string());
+    lines.iter().map(|line| format!("{line}\r\n")).collect()
+}
+
+/// Free pairs grouped by day.
+pub fn table(free_busy: &FreeBusy) -> String {
+    if free_busy.free.is_empty() {
+        return "Общих свободных пар нет\n".to_string();
+    }
+
+    let mut out = String::new();
+    let mut day = None;
+    for slot in &free_busy.free {
+        let date = slot.start.date_naive();
+        if day != Some(date) {
+            if day.is_some() {
+                out.push('\n');
+            }
+            day = Some(date);
+            let weekday = WEEKDAYS[date.weekday().num_days_from_monday() as usize];
+            writeln!(out, "{weekday}, {}", date.format("%d.%m")).unwrap();
+        }
+        writeln!(
+            out,
+            "{} пара  {}–{}",
+            slot.lesson_number,
+            slot.start.format("%H:%M"),
+            slot.end.format("%H:%M")
+        )
+        .unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::*;
+
+    #[test]
+    fn free_pairs_avoid_everyone_busy() {
+        let cfg = Config::default();
+        let mut second = lesson_json(2, 1, "FULL", "");
+        second["lessonTime"] = serde_json::json!({
+            "id": 2,
+            "lessonNumber": 2,
+            "hourStart": 10,
+            "minuteStart": 0,
+            "hourEnd": 11,
+            "minuteEnd": 35
+        });
+        let group = schedule(vec![lesson_json(1, 1, "FULL", "")]);
+        let teacher = schedule(vec![second]);
+        let attendees = [
+            Attendee {
+                who: "knt/full/351".to_string(),
+                request: Request::default(),
+                lessons: group.lessons.clone(),
+            },
+            Attendee {
+                who: "Иванов Иван Иванович".to_string(),
+                request: Request::default(),
+                lessons: teacher.lessons.clone(),
+            },
+        ];
+
+        let first = group.lessons[0].first_span(&cfg).0.date_naive();
+        let free_busy = free_busy(&cfg, &attendees, first, first + Duration::days(6));
+        assert_eq!(free_busy.busy.len(), 2);
+        assert_eq!(free_busy.busy[1].who, "Иванов Иван Иванович");
+
+        let numbers = |date: NaiveDate| -> Vec<u8> {
+            free_busy
+                .free
+                .iter()
+                .filter(|slot| slot.start.date_naive() == date)
+                .map(|slot| slot.lesson_number)
+                .collect()
+        };
+        // Only pairs 1 and 2 are known, both taken on the first day
+        assert!(numbers(first).is_empty());
+        let other = (1..7)
+            .map(|days| first + Duration::days(days))
+            .find(|date| date.weekday() != Weekday::Sun)
+            .unwrap();
+        assert_eq!(numbers(other), [1, 2]);
+        assert!(!free_busy
+            .free
+            .iter()
+            .any(|slot| slot.start.weekday() == Weekday::Sun));
+
+        let ical = vfreebusy(&free_busy, first, first, Utc::now());
+        assert!(ical.contains("BEGIN:VFREEBUSY\r\n"));
+        assert_eq!(ical.matches("FBTYPE=BUSY").count(), 2);
+        assert!(table(&free_busy).contains("2 пара  10:00–11:35"));
+
+        let today = first;
+        assert!(Query::new(Vec::new(), Vec::new(), None, None, today).is_err());
+        let query = Query::new(Vec::new(), vec![1], None, None, today).unwrap();
+        assert_eq!(query.to, today + Duration::days(6));
+        let teachers = (0..=MAX_ATTENDEES as u32).collect();
+        assert!(Query::new(Vec::new(), teachers, None, None, today).is_err());
+        assert!(Query::new(
+            Vec::new(),
+            vec![1],
+            None,
+            Some(today - Duration::days(1)),
+            today
+        )
+        .is_err());
+    }
+}
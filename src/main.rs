@@ -1,13 +1,41 @@
-use clap::{Parser, Subcommand};
+use clap::{parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
 use log::LevelFilter;
 use simple_logger::SimpleLogger;
-use std::{fs::File, io::Write, process::ExitCode};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
+mod alarm;
+mod bells;
 mod calendar;
 mod config;
+mod crawl;
+mod db;
+mod diff;
+mod expand;
+mod export;
+mod feed;
+mod filter;
+#[cfg(test)]
+mod fixtures;
+mod format;
+mod freebusy;
+mod merge;
 mod models;
+mod occurrence;
+mod overrides;
+mod profile;
 mod server;
+mod show;
+mod snapshot;
+mod subgroup;
+mod subscription;
+mod template;
 mod tracto;
+mod webhook;
 
 use config::*;
 
@@ -21,25 +49,163 @@ pub struct Cli {
 #[derive(Debug, Subcommand)]
 enum Command {
     /// Get single calendar
-    Single(Request),
+    ///
+    /// Exit codes: 64 for a bad request, 69 when Tracto is unavailable,
+    /// 73 when --no-clobber refuses to overwrite the output
+    /// and 74 for other I/O errors.
+    Single(SingleArgs),
     /// Run as web server
     Server,
     /// Clear all cache
     Prune,
+    /// Show schedule changes
+    Diff(DiffArgs),
+    /// Print lessons of today, this week or the next one
+    Show(show::ShowArgs),
+    /// Find pairs when all the groups and teachers are free
+    Free(freebusy::FreeArgs),
+    /// Show pair times of a department and lessons off them
+    Bells {
+        /// Department url, e.g. knt
+        department: String,
+    },
+    /// Prefetch every department and group
+    Crawl(crawl::CrawlArgs),
+    /// Export calendars of every group as a static site
+    Export(export::ExportArgs),
+    /// Maintain the schedule database
+    #[clap(subcommand)]
+    Db(DbCommand),
+    /// Manage saved request profiles
+    #[clap(subcommand)]
+    Profile(profile::ProfileCommand),
+}
+
+#[derive(Debug, Subcommand)]
+enum DbCommand {
+    /// Apply pending schema migrations
+    Migrate,
+    /// Show number of stored rows
+    Stats,
+    /// Delete old snapshots and fetch records, keeping the latest ones
+    /// of every group
+    Prune {
+        #[arg(long, default_value_t = 30)]
+        keep: usize,
+    },
+    /// Rebuild the database file to reclaim space
+    Vacuum,
 }
 
 #[derive(Parser, Debug)]
-pub struct Request {
+pub struct SingleArgs {
+    #[command(flatten)]
+    pub request: Request,
+    /// File or directory to write the calendar to, `-` for stdout
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// Fail instead of overwriting an existing file
+    #[arg(long)]
+    pub no_clobber: bool,
+}
+
+/// Exit codes of `calar single`, as in sysexits.h.
+mod exit_code {
+    pub const BAD_REQUEST: u8 = 64;
+    pub const UPSTREAM: u8 = 69;
+    pub const CANNOT_CREATE: u8 = 73;
+    pub const IO: u8 = 74;
+}
+
+#[derive(Parser, Debug)]
+pub struct DiffArgs {
     #[arg(short, long)]
     pub department: String,
     #[arg(short, long)]
     pub form: String,
     #[arg(short, long)]
     pub group: String,
+    /// Compare against the schedule as it was this many hours ago
+    #[arg(long, default_value_t = 24)]
+    pub since: u32,
+    /// Print changes as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Parser, Debug, Default, Clone)]
+pub struct Request {
+    #[arg(
+        short,
+        long,
+        default_value = "",
+        hide_default_value = true,
+        required_unless_present = "profile"
+    )]
+    pub department: String,
+    #[arg(
+        short,
+        long,
+        default_value = "",
+        hide_default_value = true,
+        required_unless_present = "profile"
+    )]
+    pub form: String,
+    #[arg(
+        short,
+        long,
+        default_value = "",
+        hide_default_value = true,
+        required_unless_present = "profile"
+    )]
+    pub group: String,
     #[arg(short, long, num_args(0..))]
     pub subgroups: Vec<String>,
-    #[arg(short, long)]
+    /// Include lessons for translators, `--translator false` turns
+    /// off the one saved in a profile
+    #[arg(
+        short,
+        long,
+        num_args(0..=1),
+        default_value_t = false,
+        default_missing_value = "true",
+        action = clap::ArgAction::Set
+    )]
     pub translator: bool,
+    /// Lesson filters like type:lecture or !name:физкультура, fields are
+    /// name, teacher, type, place, weekday and week
+    #[arg(long = "filter", allow_hyphen_values = true)]
+    pub filters: Vec<filter::Filter>,
+    /// Other groups merged into the calendar, e.g. knt/full/352:1_под.
+    #[arg(short, long, value_name = "DEPARTMENT/FORM/GROUP[:SUBGROUPS]")]
+    pub with: Vec<merge::GroupRef>,
+    /// Personal changes of a saved subscription
+    #[arg(skip)]
+    pub overrides: overrides::Overrides,
+    /// How lesson type is shown in event summary
+    #[arg(long, value_enum, default_value_t)]
+    pub style: calendar::SummaryStyle,
+    /// Name of the event template preset
+    #[arg(long)]
+    pub template: Option<String>,
+    /// Reminders before lessons, e.g. 15m,1h
+    #[arg(long, value_delimiter = ',')]
+    pub alarm: Vec<alarm::Reminder>,
+    /// Reminders before exams, e.g. 1d,2h
+    #[arg(long, value_delimiter = ',')]
+    pub exam_alarm: Vec<alarm::Reminder>,
+    /// Merge exams into the calendar
+    #[arg(short, long)]
+    pub exams: bool,
+    /// Stop weekly lessons when the exam session begins
+    #[arg(long, requires = "exams")]
+    pub stop_at_session: bool,
+    /// Output format
+    #[arg(long, value_enum, default_value_t)]
+    pub format: format::Format,
+    /// Saved profile filling the options not given on the command line
+    #[arg(short, long)]
+    pub profile: Option<String>,
 }
 
 #[actix_web::main]
@@ -49,42 +215,393 @@ async fn main() -> ExitCode {
         .init()
         .unwrap();
 
-    let cfg = Config::default();
-    let cli = Cli::parse();
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    // Options of the subcommand given on the command line, see `apply_profile`
+    let given = matches
+        .subcommand()
+        .map(|(_, matches)| matches.clone())
+        .unwrap_or_default();
+    let cfg = match Config::load() {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            eprintln!("Cannot load config: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
 
     match cli.command {
-        Command::Single(req) => make_single_request(cfg, req).await,
+        Command::Single(args) => make_single_request(cfg, args, &given).await,
         Command::Server => server::run_server(cfg).await,
         Command::Prune => server::prune_cache(),
+        Command::Diff(args) => show_diff(cfg, args).await,
+        Command::Show(args) => show_timetable(cfg, args, &given).await,
+        Command::Free(args) => find_free_time(cfg, args).await,
+        Command::Db(cmd) => maintain_db(cmd),
+        Command::Profile(cmd) => manage_profiles(cmd),
+        Command::Bells { department } => show_bells(&department),
+        Command::Crawl(args) => run_crawl(cfg, args).await,
+        Command::Export(args) => run_export(cfg, args).await,
     }
 }
 
-async fn make_single_request(cfg: Config, req: Request) -> ExitCode {
-    if let Err(e) = tracto::validate_request(&cfg, &req).await {
-        eprintln!("Bad request: {e}");
-        return ExitCode::FAILURE;
+fn show_bells(department: &str) -> ExitCode {
+    match bells::department_report(department) {
+        Ok(report) => {
+            print!("{}", bells::table(&report));
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Cannot check bells: {e}");
+            request_exit_code(&e)
+        }
     }
+}
 
-    let schedule = match tracto::fetch_schedule(&cfg, &req).await {
-        Ok(schedule) => schedule,
+async fn run_crawl(cfg: Config, args: crawl::CrawlArgs) -> ExitCode {
+    let options = crawl::CrawlOptions::new(&cfg.crawl, args);
+    let reports = match crawl::crawl(&cfg, &options).await {
+        Ok(reports) => reports,
         Err(e) => {
-            eprintln!("Cannot fetch schedule: {e}");
+            eprintln!("Cannot crawl: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    for report in &reports {
+        println!("{report}");
+    }
+    if reports.iter().any(|report| !report.failed.is_empty()) {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+async fn run_export(cfg: Config, args: export::ExportArgs) -> ExitCode {
+    let options = crawl::CrawlOptions::new(&cfg.crawl, args.crawl);
+    let report = match export::export(&cfg, &options, &args.out).await {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Cannot export: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    for department in &report.departments {
+        println!("{department}");
+    }
+    println!(
+        "Wrote {} file(s), {} unchanged",
+        report.written, report.unchanged
+    );
+    if report.departments.iter().any(|d| !d.failed.is_empty()) {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn manage_profiles(cmd: profile::ProfileCommand) -> ExitCode {
+    let result = match cmd {
+        profile::ProfileCommand::Add { name, profile } => profile::add(name, profile),
+        profile::ProfileCommand::Ls => profile::load().map(|profiles| {
+            for (name, profile) in profiles {
+                println!("{name}: {profile}");
+            }
+        }),
+        profile::ProfileCommand::Rm { name } => match profile::remove(&name) {
+            Ok(true) => Ok(()),
+            Ok(false) => {
+                eprintln!("No such profile: {name}");
+                return ExitCode::FAILURE;
+            }
+            Err(e) => Err(e),
+        },
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Cannot access profiles: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Options not given on the command line come from `--profile`.
+fn apply_profile(req: &mut Request, given: &ArgMatches) -> Result<(), ExitCode> {
+    let Some(name) = &req.profile else {
+        return Ok(());
+    };
+    match profile::get(name) {
+        Ok(Some(profile)) => {
+            profile.apply(req, |id| {
+                given
+                    .value_source(id)
+                    .is_some_and(|source| source != ValueSource::DefaultValue)
+            });
+            Ok(())
+        }
+        Ok(None) => {
+            eprintln!("No such profile: {name}");
+            Err(ExitCode::from(exit_code::BAD_REQUEST))
+        }
+        Err(e) => {
+            eprintln!("Cannot load profiles: {e}");
+            Err(ExitCode::from(exit_code::IO))
+        }
+    }
+}
+
+fn maintain_db(cmd: DbCommand) -> ExitCode {
+    // Opening the database applies migrations
+    let conn = match db::open() {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("Cannot open database: {e}");
             return ExitCode::FAILURE;
         }
     };
-    let calendar = schedule.to_ical(&cfg, &req);
 
-    let mut file = match File::create(server::gen_filename::<models::Schedule>(&req)) {
-        Ok(file) => file,
+    let result = match cmd {
+        DbCommand::Migrate => Ok(()),
+        DbCommand::Stats => db::stats(&conn).map(|stats| {
+            for (table, count) in stats {
+                println!("{table:>16}: {count}");
+            }
+        }),
+        DbCommand::Prune { keep } => db::prune_snapshots(&conn, keep).and_then(|snapshots| {
+            let fetches = db::prune_fetches(&conn, keep)?;
+            println!("Deleted {snapshots} snapshot(s) and {fetches} fetch record(s)");
+            Ok(())
+        }),
+        DbCommand::Vacuum => conn.execute_batch("VACUUM"),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Database error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn show_diff(cfg: Config, args: DiffArgs) -> ExitCode {
+    let req = Request {
+        department: args.department,
+        form: args.form,
+        group: args.group,
+        ..Default::default()
+    };
+    let moment = chrono::Utc::now() - chrono::Duration::hours(args.since.into());
+
+    let changes = match tracto::fetch_schedule(&cfg, &req).await {
+        Ok(current) => diff::changes_since(&req, current, moment).await,
+        Err(e) => Err(e),
+    };
+    let changes = match changes {
+        Ok(changes) => changes,
         Err(e) => {
-            eprintln!("Cannot create file: {e}");
+            eprintln!("Cannot get changes: {e}");
             return ExitCode::FAILURE;
         }
     };
-    if let Err(e) = file.write_all(calendar.to_string().as_bytes()) {
-        eprintln!("Cannot write to file: {e}");
-        return ExitCode::FAILURE;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&changes).unwrap());
+    } else {
+        println!("Changes since {}:", changes.since);
+        print!("{}", changes.diff);
+    }
+
+    ExitCode::SUCCESS
+}
+
+async fn show_timetable(cfg: Config, mut args: show::ShowArgs, given: &ArgMatches) -> ExitCode {
+    if let Err(code) = apply_profile(&mut args.request, given) {
+        return code;
     }
+    let occurrences = match show::occurrences(&cfg, &args.request).await {
+        Ok(occurrences) => occurrences,
+        Err(e) => {
+            eprintln!("Cannot fetch schedule: {e}");
+            return request_exit_code(&e);
+        }
+    };
+
+    let color = io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    print!(
+        "{}",
+        show::table(&occurrences, args.period(), show::now(), color)
+    );
+    ExitCode::SUCCESS
+}
+
+async fn find_free_time(cfg: Config, args: freebusy::FreeArgs) -> ExitCode {
+    let today = show::now().date_naive();
+    let query = match freebusy::Query::new(args.groups, args.teachers, args.from, args.to, today) {
+        Ok(query) => query,
+        Err(e) => {
+            eprintln!("Bad request: {e}");
+            return ExitCode::from(exit_code::BAD_REQUEST);
+        }
+    };
+    let attendees = match freebusy::attendees(&cfg, &query).await {
+        Ok(attendees) => attendees,
+        Err(e) => {
+            match e {
+                tracto::RequestError::Invalid(_) => eprintln!("Bad request: {e}"),
+                _ => eprintln!("Cannot fetch schedule: {e}"),
+            }
+            return request_exit_code(&e);
+        }
+    };
 
+    let free_busy = freebusy::free_busy(&cfg, &attendees, query.from, query.to);
+    if args.ical {
+        print!(
+            "{}",
+            freebusy::vfreebusy(&free_busy, query.from, query.to, chrono::Utc::now())
+        );
+    } else {
+        print!("{}", freebusy::table(&free_busy));
+    }
     ExitCode::SUCCESS
 }
+
+fn request_exit_code(e: &tracto::RequestError) -> ExitCode {
+    ExitCode::from(match e {
+        tracto::RequestError::Invalid(_) => exit_code::BAD_REQUEST,
+        tracto::RequestError::Upstream(_) => exit_code::UPSTREAM,
+        tracto::RequestError::Io(_) => exit_code::IO,
+    })
+}
+
+async fn make_single_request(cfg: Config, args: SingleArgs, given: &ArgMatches) -> ExitCode {
+    let mut req = args.request;
+    if let Err(code) = apply_profile(&mut req, given) {
+        return code;
+    }
+    let validated = match tracto::validate_request(&cfg, &mut req).await {
+        Ok(validated) => validated,
+        Err(e) => {
+            match e {
+                tracto::RequestError::Invalid(_) => eprintln!("Bad request: {e}"),
+                _ => eprintln!("Cannot validate request: {e}"),
+            }
+            return request_exit_code(&e);
+        }
+    };
+    for warning in &validated.warnings {
+        eprintln!("Warning: {warning}");
+    }
+
+    let schedule = merge::from_fetched(&cfg, &req, validated.schedule, validated.with);
+    let (contents, filename) = if req.exams {
+        let exams = match merge::exams(&cfg, &req).await {
+            Ok(exams) => exams,
+            Err(e) => {
+                eprintln!("Cannot fetch exams: {e}");
+                return request_exit_code(&e);
+            }
+        };
+        let combined = calendar::Combined { schedule, exams };
+        (
+            format::render(&combined, &cfg, &req),
+            server::gen_filename::<calendar::Combined>(&req),
+        )
+    } else {
+        (
+            format::render(&schedule, &cfg, &req),
+            server::gen_filename::<models::Schedule>(&req),
+        )
+    };
+
+    let path = match args.output {
+        Some(output) if output == Path::new("-") => {
+            return match io::stdout().lock().write_all(contents.as_bytes()) {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("Cannot write to stdout: {e}");
+                    ExitCode::from(exit_code::IO)
+                }
+            };
+        }
+        Some(output) if output.is_dir() => output.join(filename),
+        Some(output) => output,
+        None => PathBuf::from(filename),
+    };
+
+    match write_output(&path, contents.as_bytes(), args.no_clobber) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            eprintln!("{} already exists", path.display());
+            ExitCode::from(exit_code::CANNOT_CREATE)
+        }
+        Err(e) => {
+            eprintln!("Cannot write {}: {e}", path.display());
+            ExitCode::from(exit_code::IO)
+        }
+    }
+}
+
+/// Writes the whole file or nothing, so readers never see a half
+/// written calendar.
+fn write_output(path: &Path, contents: &[u8], no_clobber: bool) -> io::Result<()> {
+    if no_clobber {
+        let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        return file.write_all(contents);
+    }
+
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    fs::write(&tmp_path, contents)?;
+    fs::rename(&tmp_path, path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp_path);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cli_is_consistent() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn translator_can_be_turned_off() {
+        let request = |args: &[&str]| {
+            let args = ["calar", "single", "--profile", "p"].iter().chain(args);
+            let matches = Cli::command().get_matches_from(args);
+            let Command::Single(single) = Cli::from_arg_matches(&matches).unwrap().command else {
+                unreachable!()
+            };
+            let (_, given) = matches.subcommand().unwrap();
+            let given = given.value_source("translator") == Some(ValueSource::CommandLine);
+            (single.request.translator, given)
+        };
+        assert_eq!(request(&[]), (false, false));
+        assert_eq!(request(&["-t"]), (true, true));
+        assert_eq!(request(&["--translator", "false"]), (false, true));
+    }
+
+    #[test]
+    fn no_clobber_keeps_existing_file() {
+        let path = std::env::temp_dir().join(format!("calar-output-{}.ics", std::process::id()));
+
+        write_output(&path, b"first", true).unwrap();
+        let e = write_output(&path, b"second", true).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read(&path).unwrap(), b"first");
+
+        write_output(&path, b"third", false).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"third");
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,313 @@
+use crate::{
+    models::{ExamEvent, ExamList, Lesson, Schedule},
+    tracto::{self, RequestResult},
+    Config, Request,
+};
+
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// Another group merged into the calendar, written as
+/// `knt/full/352` or `knt/full/352:1_под.,анг.ст.3`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct GroupRef {
+    pub department: String,
+    pub form: String,
+    pub group: String,
+    pub subgroups: Vec<String>,
+}
+
+impl FromStr for GroupRef {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (path, subgroups) = s.split_once(':').unwrap_or((s, ""));
+        let parts: Vec<&str> = path.split('/').map(str::trim).collect();
+        let [department, form, group] = parts[..] else {
+            return Err(format!(
+                "Incorrect group {s:?}, use department/form/group[:subgroups]"
+            ));
+        };
+        if [department, form, group].iter().any(|part| part.is_empty()) {
+            return Err(format!("Incomplete group {s:?}"));
+        }
+
+        Ok(Self {
+            department: department.to_string(),
+            form: form.to_string(),
+            group: group.to_string(),
+            subgroups: subgroups
+                .split(',')
+                .map(str::trim)
+                .filter(|sg| !sg.is_empty())
+                .map(str::to_string)
+                .collect(),
+        })
+    }
+}
+
+impl TryFrom<String> for GroupRef {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<GroupRef> for String {
+    fn from(value: GroupRef) -> Self {
+        value.to_string()
+    }
+}
+
+impl fmt::Display for GroupRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}/{}", self.department, self.form, self.group)?;
+        if !self.subgroups.is_empty() {
+            write!(f, ":{}", self.subgroups.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+impl GroupRef {
+    /// Request of this group with the other options of `base`.
+    pub fn request(&self, base: &Request) -> Request {
+        Request {
+            department: self.department.clone(),
+            form: self.form.clone(),
+            group: self.group.clone(),
+            subgroups: self.subgroups.clone(),
+            with: Vec::new(),
+            ..base.clone()
+        }
+    }
+}
+
+impl Request {
+    /// Requests of every group of the calendar, starting with the main one.
+    pub fn members(&self) -> Vec<Request> {
+        let main = Request {
+            with: Vec::new(),
+            ..self.clone()
+        };
+        std::iter::once(main)
+            .chain(self.with.iter().map(|group| group.request(self)))
+            .collect()
+    }
+}
+
+/// Shared lectures come either as the same lesson or as copies
+/// with the same name, subgroup, time, place and teacher.
+pub fn same_lesson(a: &Lesson, b: &Lesson) -> bool {
+    a.id == b.id
+        || (a.day.day_number == b.day.day_number
+            && a.lesson_time.lesson_number == b.lesson_time.lesson_number
+            && a.week_type == b.week_type
+            && a.place.trim() == b.place.trim()
+            && a.teacher.id == b.teacher.id
+            && a.name == b.name
+            && a.sub_group.trim() == b.sub_group.trim())
+}
+
+fn same_exam(a: &ExamEvent, b: &ExamEvent) -> bool {
+    a.id == b.id
+        || ((a.year.as_str(), a.month.number, a.day, a.hour, a.minute)
+            == (b.year.as_str(), b.month.number, b.day, b.hour, b.minute)
+            && a.place.trim() == b.place.trim()
+            && a.subject_name == b.subject_name)
+}
+
+/// Adds `item` of `group` unless another group has it already, then it only
+/// gets the label. Items of one group are never merged with each other.
+fn push_unique<T: Clone>(
+    merged: &mut Vec<(T, Vec<String>)>,
+    item: &T,
+    group: &str,
+    same: impl Fn(&T, &T) -> bool,
+) {
+    let shared = merged
+        .iter_mut()
+        .find(|(other, groups)| !groups.iter().any(|g| g == group) && same(other, item));
+    match shared {
+        Some((_, groups)) => groups.push(group.to_string()),
+        None => merged.push((item.clone(), vec![group.to_string()])),
+    }
+}
+
+fn label(name: &str, groups: &[String]) -> String {
+    format!("{name} [{}]", groups.join(", "))
+}
+
+/// Requested lessons of every group in one schedule, labeled with
+/// their groups.
+pub fn merge_schedules(cfg: &Config, members: &[(Request, Schedule)]) -> Schedule {
+    let mut merged = Vec::new();
+    for (req, schedule) in members {
+        for lesson in schedule.requested_lessons(cfg, req) {
+            push_unique(&mut merged, lesson, &req.group, same_lesson);
+        }
+    }
+
+    let (_, first) = &members[0];
+    Schedule {
+        lessons: merged
+            .into_iter()
+            .map(|(mut lesson, groups)| {
+                lesson.name = label(&lesson.name, &groups);
+                lesson
+            })
+            .collect(),
+        student_group: first.student_group.clone(),
+        day: first.day.clone(),
+    }
+}
+
+pub fn merge_exams(members: &[(Request, ExamList)]) -> ExamList {
+    let mut merged = Vec::new();
+    for (req, exams) in members {
+        for exam in &exams.exam_period_events {
+            push_unique(&mut merged, exam, &req.group, same_exam);
+        }
+    }
+
+    ExamList {
+        exam_period_events: merged
+            .into_iter()
+            .map(|(mut exam, groups)| {
+                exam.subject_name = label(&exam.subject_name, &groups);
+                exam
+            })
+            .collect(),
+        student_group: members[0].1.student_group.clone(),
+    }
+}
+
+/// Schedule of the request with personal overrides, merged if it
+/// lists several groups.
+pub async fn schedule(cfg: &Config, req: &Request) -> RequestResult<Schedule> {
+    let main = tracto::fetch_schedule(cfg, req).await?;
+    let mut with = Vec::new();
+    for group in &req.with {
+        with.push(tracto::fetch_schedule(cfg, &group.request(req)).await?);
+    }
+    Ok(from_fetched(cfg, req, main, with))
+}
+
+/// Same as [`schedule`] for schedules fetched already: the one of
+/// the main group and those of the groups in `with`, in order.
+pub fn from_fetched(
+    cfg: &Config,
+    req: &Request,
+    mut main: Schedule,
+    with: Vec<Schedule>,
+) -> Schedule {
+    if req.with.is_empty() {
+        req.overrides.apply(&mut main);
+        return main;
+    }
+    let mut members = Vec::new();
+    let fetched = std::iter::once(main).chain(with);
+    for (member, mut schedule) in req.members().into_iter().zip(fetched) {
+        // Lessons have to be moved between subgroups before merging
+        req.overrides.adjust(&mut schedule);
+        members.push((member, schedule));
+    }
+    let mut schedule = merge_schedules(cfg, &members);
+    req.overrides.add_events(&mut schedule);
+    schedule
+}
+
+/// Exams of the request, merged if it lists several groups.
+pub async fn exams(cfg: &Config, req: &Request) -> RequestResult<ExamList> {
+    if req.with.is_empty() {
+        return tracto::fetch_exam(cfg, req).await;
+    }
+    let mut members = Vec::new();
+    for member in req.members() {
+        let exams = tracto::fetch_exam(cfg, &member).await?;
+        members.push((member, exams));
+    }
+    Ok(merge_exams(&members))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, lesson_json};
+
+    #[test]
+    fn shared_lessons_are_merged() {
+        let cfg = Config::default();
+        let mut req = Request {
+            department: "knt".to_string(),
+            form: "full".to_string(),
+            group: "351".to_string(),
+            subgroups: vec!["1_под.".to_string()],
+            with: vec!["knt/full/352:2_под.".parse().unwrap()],
+            ..Default::default()
+        };
+        let mut own = lesson_json(4, 3, "FULL", "");
+        own["place"] = "9 корпус 101".into();
+        // Another subject of the same subgroup at the same time and place
+        let mut other = lesson_json(6, 2, "FULL", "1_под.");
+        other["lessonType"] = "PRACTICE".into();
+        // A shared lecture copied into each group under its own id
+        let mut copy = lesson_json(9, 5, "FULL", "");
+        copy["name"] = "Предмет 8".into();
+        let mut second = fixtures::schedule(vec![
+            lesson_json(1, 1, "FULL", ""),
+            lesson_json(3, 2, "FULL", "2_под."),
+            copy,
+            own,
+        ]);
+        for lesson in &mut second.lessons {
+            lesson.student_group.group_number = "352".to_string();
+        }
+        let members: Vec<_> = req
+            .members()
+            .into_iter()
+            .zip([
+                fixtures::schedule(vec![
+                    lesson_json(1, 1, "FULL", ""),
+                    lesson_json(2, 2, "FULL", "1_под."),
+                    other,
+                    lesson_json(5, 4, "FULL", "2_под."),
+                    lesson_json(8, 5, "FULL", ""),
+                ]),
+                second,
+            ])
+            .collect();
+        assert_eq!(members[1].0.subgroups, ["2_под."]);
+        assert!(members[1].0.with.is_empty());
+
+        let merged = merge_schedules(&cfg, &members);
+        let names: Vec<&str> = merged.lessons.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "Предмет 1 [351, 352]",
+                "Предмет 2 [351]",
+                "Предмет 6 [351]",
+                "Предмет 8 [351, 352]",
+                "Предмет 3 [352]",
+                "Предмет 4 [352]"
+            ]
+        );
+        // Lessons of 352 are checked against its own subgroups
+        assert_eq!(merged.requested_lessons(&cfg, &req).count(), 6);
+
+        req.with.clear();
+        assert_eq!(req.members().len(), 1);
+        assert_eq!(
+            "knt/full/352:1_под., анг.ст.3"
+                .parse::<GroupRef>()
+                .unwrap()
+                .to_string(),
+            "knt/full/352:1_под.,анг.ст.3"
+        );
+        assert!("knt/352".parse::<GroupRef>().is_err());
+    }
+}
@@ -1,6 +1,112 @@
+use crate::{calendar::SummaryStyle, config::Config};
+
 use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, sync::Mutex};
+
+/// Occurrences of values Tracto sent which calar does not know about,
+/// keyed by enum name and then by value.
+static UNKNOWN_VALUES: Mutex<BTreeMap<&'static str, BTreeMap<String, u64>>> =
+    Mutex::new(BTreeMap::new());
+
+fn record_unknown(kind: &'static str, value: &str) {
+    let mut unknown = UNKNOWN_VALUES.lock().unwrap();
+    let count = unknown
+        .entry(kind)
+        .or_default()
+        .entry(value.to_string())
+        .or_insert(0);
+    if *count == 0 {
+        log::warn!("Unknown {kind} value: {value:?}");
+    }
+    *count += 1;
+}
+
+/// Snapshot of unknown values counters.
+pub fn unknown_values() -> BTreeMap<&'static str, BTreeMap<String, u64>> {
+    UNKNOWN_VALUES.lock().unwrap().clone()
+}
+
+/// Defines a string-backed enum for a Tracto field. Values missing from
+/// the list are kept in `Unknown` and reported to `record_unknown`.
+macro_rules! tracto_enum {
+    ($(#[$meta:meta])* $name:ident { $($variant:ident => $value:literal,)+ }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+        #[serde(from = "String", into = "String")]
+        pub enum $name {
+            $($variant,)+
+            Unknown(String),
+        }
+
+        impl $name {
+            /// Parses a known value, ignoring case.
+            pub fn from_known(value: &str) -> Option<Self> {
+                match value.trim().to_uppercase().as_str() {
+                    $($value => Some(Self::$variant),)+
+                    _ => None,
+                }
+            }
+
+            pub fn as_str(&self) -> &str {
+                match self {
+                    $(Self::$variant => $value,)+
+                    Self::Unknown(value) => value.as_str(),
+                }
+            }
+        }
+
+        impl From<String> for $name {
+            fn from(value: String) -> Self {
+                Self::from_known(&value).unwrap_or_else(|| {
+                    record_unknown(stringify!($name), &value);
+                    Self::Unknown(value)
+                })
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(value: $name) -> Self {
+                value.as_str().to_string()
+            }
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "{}", self.as_str())
+            }
+        }
+    };
+}
+
+tracto_enum!(LessonType {
+    Lecture => "LECTURE",
+    Practice => "PRACTICE",
+    Laboratory => "LABORATORY",
+    Seminar => "SEMINAR",
+});
 
-#[derive(Debug, Serialize, Deserialize)]
+tracto_enum!(
+    /// Lessons either occur every week or only on numerator/denominator ones.
+    WeekType {
+        Full => "FULL",
+        Nom => "NOM",
+        Denom => "DENOM",
+    }
+);
+
+tracto_enum!(EducationForm {
+    Full => "FULL",
+    Extramural => "EXTRAMURAL",
+});
+
+tracto_enum!(ExamType {
+    Consultation => "CONSULTATION",
+    Exam => "EXAM",
+    Midterm => "MIDTERM",
+    MidtermWithMark => "MIDTERM_WITH_MARK",
+});
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Schedule {
     pub lessons: Vec<Lesson>,
@@ -8,7 +114,7 @@ pub struct Schedule {
     pub day: Day,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Lesson {
     pub id: u32,
@@ -20,24 +126,25 @@ pub struct Lesson {
     pub day: Day,
     pub lesson_time: LessonTime,
     pub teacher: Teacher,
-    pub week_type: String,
-    pub lesson_type: String,
+    pub week_type: WeekType,
+    pub lesson_type: LessonType,
     pub updated_timestamp: u32,
     pub begin_timestamp: Option<u32>,
     pub end_timestamp: Option<u32>,
 }
 
 impl Lesson {
-    pub fn summary(&self) -> String {
-        let type_letter = match self.lesson_type.as_str() {
-            "LECTURE" => 'Л',
-            _ => 'П',
-        };
-        format!("{} ({})", self.name, type_letter)
+    pub fn summary(&self, cfg: &Config, style: SummaryStyle) -> String {
+        let label = cfg.lesson_type_label(&self.lesson_type);
+        match style {
+            SummaryStyle::Short => format!("{} ({})", self.name, label.short),
+            SummaryStyle::Full => format!("{} ({})", self.name, label.full),
+            SummaryStyle::None => self.name.clone(),
+        }
     }
 }
 
-#[derive(PartialEq, Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Department {
     pub id: u32,
@@ -46,18 +153,18 @@ pub struct Department {
     pub url: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct StudentGroup {
     pub id: u32,
     pub group_number: String,
     pub group_number_rus: String,
     pub department: Department,
-    pub education_form: String,
+    pub education_form: EducationForm,
     pub group_type: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Day {
     pub id: Option<u32>,
@@ -65,7 +172,7 @@ pub struct Day {
     pub week_day: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct LessonTime {
     pub id: u32,
@@ -76,7 +183,7 @@ pub struct LessonTime {
     pub minute_end: u32,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Teacher {
     pub id: u32,
     pub surname: String,
@@ -90,24 +197,30 @@ impl Teacher {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DepartmentsList {
     pub departments_list: Vec<Department>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupsList {
+    pub groups_list: Vec<StudentGroup>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ExamList {
     pub exam_period_events: Vec<ExamEvent>,
     pub student_group: StudentGroup,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ExamEvent {
     pub id: u32,
-    pub exam_period_event_type: String,
+    pub exam_period_event_type: ExamType,
     pub day: u32,
     pub month: Month,
     pub year: String,
@@ -121,21 +234,40 @@ pub struct ExamEvent {
 
 impl ExamEvent {
     pub fn summary(&self) -> String {
-        let type_letter = match self.exam_period_event_type.as_str() {
-            "CONSULTATION" => "Консультация",
-            "EXAM" => "Экзамен",
-            "MIDTERM_WITH_MARK" => "Зачет с оценкой",
-            _ => "Зачет",
+        let type_letter = match &self.exam_period_event_type {
+            ExamType::Consultation => "Консультация",
+            ExamType::Exam => "Экзамен",
+            ExamType::Midterm => "Зачет",
+            ExamType::MidtermWithMark => "Зачет с оценкой",
+            ExamType::Unknown(value) => value.as_str(),
         };
         format!("{} ({})", self.subject_name, type_letter)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Month {
     pub number: u32,
     pub rus_nominative: String,
     pub rus_genitive: String,
     pub eng: String,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_values_are_kept_and_counted() {
+        let known: LessonType = serde_json::from_str("\"LECTURE\"").unwrap();
+        assert_eq!(known, LessonType::Lecture);
+
+        for _ in 0..2 {
+            let unknown: LessonType = serde_json::from_str("\"WORKSHOP\"").unwrap();
+            assert_eq!(unknown, LessonType::Unknown("WORKSHOP".to_string()));
+            assert_eq!(serde_json::to_string(&unknown).unwrap(), "\"WORKSHOP\"");
+        }
+        assert_eq!(unknown_values()["LessonType"]["WORKSHOP"], 2);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::{
+    calendar::Combined,
+    expand::Term,
+    models::{ExamEvent, ExamList, Lesson, Schedule},
+    Config, Request,
+};
+
+use chrono::DateTime;
+use chrono_tz::Tz;
+use serde::{Serialize, Serializer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Lesson,
+    Exam,
+}
+
+/// Single occurrence of a lesson or an exam, as shown in calendar.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Occurrence {
+    pub uid: String,
+    pub kind: Kind,
+    #[serde(serialize_with = "rfc3339")]
+    pub start: DateTime<Tz>,
+    #[serde(serialize_with = "rfc3339")]
+    pub end: DateTime<Tz>,
+    pub summary: String,
+    pub description: String,
+    pub location: String,
+    pub category: String,
+}
+
+pub fn rfc3339<S: Serializer>(moment: &DateTime<Tz>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&moment.to_rfc3339())
+}
+
+impl Lesson {
+    /// Every occurrence of the lesson in `term`, the same ones the
+    /// RRULE and EXDATE of its event describe.
+    pub fn occurrences(&self, cfg: &Config, request: &Request, term: &Term) -> Vec<Occurrence> {
+        let text = self.text(cfg, request);
+        let category = &cfg.lesson_type_label(&self.lesson_type).full;
+
+        self.expand(cfg, term)
+            .spans
+            .into_iter()
+            .map(|(start, end)| Occurrence {
+                uid: self.uid(),
+                kind: Kind::Lesson,
+                start,
+                end,
+                summary: text.summary.clone(),
+                description: text.description.clone(),
+                location: text.location.clone(),
+                category: category.clone(),
+            })
+            .collect()
+    }
+}
+
+impl ExamEvent {
+    pub fn occurrence(&self, cfg: &Config, request: &Request) -> Option<Occurrence> {
+        let (start, end) = self.span(cfg)?;
+        let text = self.text(cfg, request);
+        Some(Occurrence {
+            uid: self.uid(),
+            kind: Kind::Exam,
+            start,
+            end,
+            summary: text.summary,
+            description: text.description,
+            location: text.location,
+            category: cfg
+                .exams
+                .styles
+                .get(&self.exam_period_event_type)
+                .map(|style| style.category.clone())
+                .unwrap_or_default(),
+        })
+    }
+}
+
+fn sorted(mut occurrences: Vec<Occurrence>) -> Vec<Occurrence> {
+    occurrences.sort_by(|a, b| (a.start, &a.uid).cmp(&(b.start, &b.uid)));
+    occurrences
+}
+
+impl Schedule {
+    pub fn occurrences(&self, cfg: &Config, request: &Request) -> Vec<Occurrence> {
+        self.occurrences_in(cfg, request, &Term::new(cfg))
+    }
+
+    /// Occurrences of requested lessons in `term`, see `Term::between`.
+    pub fn occurrences_in(&self, cfg: &Config, request: &Request, term: &Term) -> Vec<Occurrence> {
+        sorted(
+            self.requested_lessons(cfg, request)
+                .flat_map(|lesson| lesson.occurrences(cfg, request, term))
+                .collect(),
+        )
+    }
+}
+
+impl ExamList {
+    pub fn occurrences(&self, cfg: &Config, request: &Request) -> Vec<Occurrence> {
+        sorted(
+            self.exam_period_events
+                .iter()
+                .filter_map(|exam| exam.occurrence(cfg, request))
+                .collect(),
+        )
+    }
+}
+
+impl Combined {
+    pub fn occurrences(&self, cfg: &Config, request: &Request) -> Vec<Occurrence> {
+        let term = self.term(cfg, request);
+        let mut occurrences = self.schedule.occurrences_in(cfg, request, &term);
+        occurrences.extend(self.exams.occurrences(cfg, request));
+        sorted(occurrences)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::*;
+
+    #[test]
+    fn occurrences_follow_week_type() {
+        let cfg = Config::default();
+        let schedule = schedule(vec![
+            lesson_json(1, 1, "FULL", ""),
+            lesson_json(2, 2, "NOM", ""),
+            lesson_json(3, 3, "DENOM", ""),
+        ]);
+        let occurrences = schedule.occurrences(&cfg, &Request::default());
+        let count = |uid: &str| occurrences.iter().filter(|o| o.uid == uid).count();
+
+        let full = count("lesson-1@calar");
+        assert!(full > 10);
+        assert!(count("lesson-2@calar").abs_diff(full / 2) <= 1);
+        assert!(count("lesson-3@calar").abs_diff(full / 2) <= 1);
+        assert!(occurrences.windows(2).all(|w| w[0].start <= w[1].start));
+
+        let first_nom = occurrences
+            .iter()
+            .find(|o| o.uid == "lesson-2@calar")
+            .unwrap();
+        let first_denom = occurrences
+            .iter()
+            .find(|o| o.uid == "lesson-3@calar")
+            .unwrap();
+        // Tuesday and Wednesday lessons of different weeks
+        let days_between = (first_denom.start - first_nom.start).num_days();
+        assert_eq!((days_between - 1).abs(), 7);
+    }
+}
@@ -0,0 +1,273 @@
+use crate::models::{Day, Lesson, LessonTime, LessonType, Schedule, Teacher, WeekType};
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Ids of personal events start here, far above the ids Tracto gives.
+const PERSONAL_ID_BASE: u32 = 4_000_000_000;
+/// Largest id of a personal event, so that its lesson id fits in `u32`.
+const MAX_EVENT_ID: u32 = u32::MAX - PERSONAL_ID_BASE;
+
+/// Personal changes of a saved subscription, layered over the schedule
+/// every time it is fetched.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Overrides {
+    /// Changes of lessons by `Lesson.id`
+    pub lessons: BTreeMap<u32, LessonOverride>,
+    /// Weekly events added to the schedule
+    pub events: Vec<PersonalEvent>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LessonOverride {
+    pub hide: bool,
+    /// Attend the lesson as if in this subgroup, empty for the whole group
+    pub sub_group: Option<String>,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PersonalEvent {
+    /// Stable number the event UID is made of, so removing or reordering
+    /// events keeps the others. Assigned when overrides are saved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u32>,
+    pub name: String,
+    /// 1 is Monday
+    pub day: u32,
+    pub hour_start: u32,
+    pub minute_start: u32,
+    pub hour_end: u32,
+    pub minute_end: u32,
+    /// Every week unless set
+    #[serde(default)]
+    pub week_type: Option<WeekType>,
+    #[serde(default)]
+    pub place: String,
+    /// Shown like a practice unless set
+    #[serde(default)]
+    pub lesson_type: Option<LessonType>,
+}
+
+impl Overrides {
+    pub fn is_empty(&self) -> bool {
+        self.lessons.is_empty() && self.events.is_empty()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let mut ids = std::collections::BTreeSet::new();
+        for event in &self.events {
+            if let Some(id) = event.id {
+                if id > MAX_EVENT_ID {
+                    return Err(format!("Too large id of {:?}", event.name));
+                }
+                if !ids.insert(id) {
+                    return Err(format!("Duplicate id {id} of {:?}", event.name));
+                }
+            }
+            if !(1..=7).contains(&event.day) {
+                return Err(format!("Incorrect day of {:?}, use 1 to 7", event.name));
+            }
+            let start = (event.hour_start, event.minute_start);
+            let end = (event.hour_end, event.minute_end);
+            if start.0 > 23 || start.1 > 59 || end.0 > 23 || end.1 > 59 || start >= end {
+                return Err(format!("Incorrect time of {:?}", event.name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Ids of the events, events without one get the next free ids.
+    /// `None` if no id up to `MAX_EVENT_ID` is left.
+    fn event_ids(&self) -> Vec<Option<u32>> {
+        let mut next = self
+            .events
+            .iter()
+            .filter_map(|event| event.id)
+            .max()
+            .map_or(Some(0), |id| id.checked_add(1));
+        self.events
+            .iter()
+            .map(|event| {
+                event.id.or_else(|| {
+                    let id = next.filter(|id| *id <= MAX_EVENT_ID);
+                    next = id.and_then(|id| id.checked_add(1));
+                    id
+                })
+            })
+            .collect()
+    }
+
+    /// Gives every event without an id a new one.
+    pub fn assign_ids(&mut self) -> Result<(), String> {
+        let ids = self.event_ids();
+        if let Some((event, _)) = self.events.iter().zip(&ids).find(|(_, id)| id.is_none()) {
+            return Err(format!("No free id left for {:?}", event.name));
+        }
+        for (event, id) in self.events.iter_mut().zip(ids) {
+            event.id = id;
+        }
+        Ok(())
+    }
+
+    /// Hides, moves and renames lessons of the schedule.
+    pub fn adjust(&self, schedule: &mut Schedule) {
+        schedule.lessons.retain(|lesson| {
+            !self
+                .lessons
+                .get(&lesson.id)
+                .is_some_and(|change| change.hide)
+        });
+        for lesson in &mut schedule.lessons {
+            let Some(change) = self.lessons.get(&lesson.id) else {
+                continue;
+            };
+            if let Some(sub_group) = &change.sub_group {
+                lesson.sub_group.clone_from(sub_group);
+            }
+            if let Some(name) = &change.name {
+                lesson.name.clone_from(name);
+            }
+        }
+    }
+
+    /// Adds personal events to the schedule as lessons of the whole group.
+    pub fn add_events(&self, schedule: &mut Schedule) {
+        for (event, id) in self.events.iter().zip(self.event_ids()) {
+            let Some(id) = id.and_then(|id| PERSONAL_ID_BASE.checked_add(id)) else {
+                log::warn!(
+                    "Skipping personal event {:?} without a valid id",
+                    event.name
+                );
+                continue;
+            };
+            let lesson = Lesson {
+                id,
+                name: event.name.clone(),
+                place: event.place.clone(),
+                department: schedule.student_group.department.clone(),
+                student_group: schedule.student_group.clone(),
+                sub_group: String::new(),
+                day: Day {
+                    id: None,
+                    day_number: event.day,
+                    week_day: None,
+                },
+                lesson_time: LessonTime {
+                    id: 0,
+                    lesson_number: 0,
+                    hour_start: event.hour_start,
+                    minute_start: event.minute_start,
+                    hour_end: event.hour_end,
+                    minute_end: event.minute_end,
+                },
+                teacher: Teacher {
+                    id: 0,
+                    surname: String::new(),
+                    name: String::new(),
+                    patronymic: String::new(),
+                },
+                week_type: event.week_type.clone().unwrap_or(WeekType::Full),
+                lesson_type: event.lesson_type.clone().unwrap_or(LessonType::Practice),
+                updated_timestamp: 0,
+                begin_timestamp: None,
+                end_timestamp: None,
+            };
+            schedule.lessons.push(lesson);
+        }
+    }
+
+    pub fn apply(&self, schedule: &mut Schedule) {
+        self.adjust(schedule);
+        self.add_events(schedule);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures::*, Config, Request};
+
+    #[test]
+    fn overrides_change_requested_lessons() {
+        let cfg = Config::default();
+        let mut schedule = schedule(vec![
+            lesson_json(1, 1, "FULL", ""),
+            lesson_json(2, 2, "FULL", "1_под."),
+            lesson_json(3, 2, "FULL", "2_под."),
+        ]);
+        let overrides: Overrides = serde_json::from_str(
+            r#"{
+                "lessons": {
+                    "1": { "hide": true },
+                    "3": { "sub_group": "", "name": "Лабы со второй" }
+                },
+                "events": [{
+                    "name": "Английский с репетитором",
+                    "day": 6,
+                    "hour_start": 10,
+                    "minute_start": 0,
+                    "hour_end": 11,
+                    "minute_end": 30,
+                    "week_type": "NOM"
+                }]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(overrides.validate(), Ok(()));
+        overrides.apply(&mut schedule);
+
+        let request = Request {
+            subgroups: vec!["1_под.".to_string()],
+            ..Default::default()
+        };
+        let names: Vec<&str> = schedule
+            .requested_lessons(&cfg, &request)
+            .map(|lesson| lesson.name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            ["Предмет 2", "Лабы со второй", "Английский с репетитором"]
+        );
+        let event = schedule.lessons.last().unwrap();
+        assert_eq!(event.uid(), format!("lesson-{PERSONAL_ID_BASE}@calar"));
+        assert_eq!(event.week_type, WeekType::Nom);
+
+        // Removing an event keeps the UIDs of the others
+        let mut saved = overrides.clone();
+        saved.events.insert(0, saved.events[0].clone());
+        saved.events[0].name = "Бассейн".to_string();
+        saved.assign_ids().unwrap();
+        assert_eq!(
+            saved.events.iter().map(|e| e.id).collect::<Vec<_>>(),
+            [Some(0), Some(1)]
+        );
+        saved.events.remove(0);
+        let mut personal = crate::fixtures::schedule(Vec::new());
+        saved.add_events(&mut personal);
+        assert_eq!(
+            personal.lessons[0].uid(),
+            format!("lesson-{}@calar", PERSONAL_ID_BASE + 1)
+        );
+        saved.events.push(saved.events[0].clone());
+        assert!(saved.validate().is_err());
+
+        let mut late = overrides.clone();
+        late.events[0].hour_end = 9;
+        assert!(late.validate().is_err());
+
+        // Ids after the largest one run out at the end of the range
+        let mut last = overrides;
+        last.events.push(last.events[0].clone());
+        last.events[0].id = Some(MAX_EVENT_ID);
+        assert_eq!(last.validate(), Ok(()));
+        let mut personal = crate::fixtures::schedule(Vec::new());
+        last.add_events(&mut personal);
+        assert_eq!(personal.lessons.len(), 1);
+        assert_eq!(personal.lessons[0].id, u32::MAX);
+        assert!(last.assign_ids().is_err());
+        assert_eq!(last.events[1].id, None);
+    }
+}
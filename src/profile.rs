@@ -0,0 +1,189 @@
+use crate::{config, format::Format, Request};
+
+use clap::{Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, io, path::PathBuf};
+
+/// Saved request options, used with `--profile`.
+#[derive(Parser, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    #[arg(short, long)]
+    pub department: String,
+    #[arg(short, long)]
+    pub form: String,
+    #[arg(short, long)]
+    pub group: String,
+    #[arg(short, long, num_args(0..))]
+    #[serde(default)]
+    pub subgroups: Vec<String>,
+    #[arg(short, long)]
+    #[serde(default)]
+    pub translator: bool,
+    /// Output format
+    #[arg(long, value_enum, default_value_t)]
+    #[serde(default)]
+    pub format: Format,
+    /// Name of the event template preset
+    #[arg(long)]
+    #[serde(default)]
+    pub template: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ProfileCommand {
+    /// Save a profile, replacing the one with the same name
+    Add {
+        name: String,
+        #[command(flatten)]
+        profile: Profile,
+    },
+    /// List saved profiles
+    Ls,
+    /// Delete a profile
+    Rm { name: String },
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}/{}", self.department, self.form, self.group)?;
+        if !self.subgroups.is_empty() {
+            write!(f, " [{}]", self.subgroups.join(", "))?;
+        }
+        if self.translator {
+            write!(f, " translator")?;
+        }
+        if self.format != Format::default() {
+            write!(f, " {}", self.format.extension())?;
+        }
+        if let Some(template) = &self.template {
+            write!(f, " template={template}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Profile {
+    /// Fills the options of `req` for which `given` tells they were
+    /// not given on the command line.
+    pub fn apply(&self, req: &mut Request, given: impl Fn(&str) -> bool) {
+        for (id, field, saved) in [
+            ("department", &mut req.department, &self.department),
+            ("form", &mut req.form, &self.form),
+            ("group", &mut req.group, &self.group),
+        ] {
+            if !given(id) {
+                field.clone_from(saved);
+            }
+        }
+        if !given("subgroups") {
+            req.subgroups.clone_from(&self.subgroups);
+        }
+        if !given("translator") {
+            req.translator = self.translator;
+        }
+        if !given("format") {
+            req.format = self.format;
+        }
+        if !given("template") {
+            req.template.clone_from(&self.template);
+        }
+    }
+}
+
+/// Profiles are kept next to the config file.
+fn profiles_path() -> io::Result<PathBuf> {
+    let dir = match std::env::var_os(config::CONFIG_ENV) {
+        Some(path) => PathBuf::from(path).parent().map(|dir| dir.to_path_buf()),
+        None => config::get_config_dir(),
+    };
+    dir.map(|dir| dir.join("profiles.json"))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No config directory for profiles"))
+}
+
+pub fn load() -> io::Result<BTreeMap<String, Profile>> {
+    let path = profiles_path()?;
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+}
+
+fn save(profiles: &BTreeMap<String, Profile>) -> io::Result<()> {
+    let path = profiles_path()?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, serde_json::to_vec_pretty(profiles)?)
+}
+
+pub fn get(name: &str) -> io::Result<Option<Profile>> {
+    Ok(load()?.remove(name))
+}
+
+pub fn add(name: String, profile: Profile) -> io::Result<()> {
+    let mut profiles = load()?;
+    profiles.insert(name, profile);
+    save(&profiles)
+}
+
+/// Returns whether the profile existed.
+pub fn remove(name: &str) -> io::Result<bool> {
+    let mut profiles = load()?;
+    let existed = profiles.remove(name).is_some();
+    if existed {
+        save(&profiles)?;
+    }
+    Ok(existed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_line_overrides_profile() {
+        let profile = Profile {
+            department: "knt".to_string(),
+            form: "full".to_string(),
+            group: "351".to_string(),
+            subgroups: vec!["1_под.".to_string()],
+            translator: true,
+            format: Format::Csv,
+            template: Some("initials".to_string()),
+        };
+
+        let mut req = Request {
+            group: "451".to_string(),
+            ..Default::default()
+        };
+        profile.apply(&mut req, |id| id == "group");
+        assert_eq!(
+            (
+                req.department.as_str(),
+                req.form.as_str(),
+                req.group.as_str()
+            ),
+            ("knt", "full", "451")
+        );
+        assert_eq!(req.subgroups, ["1_под."]);
+        assert!(req.translator);
+        assert_eq!(req.format, Format::Csv);
+        assert_eq!(req.template.as_deref(), Some("initials"));
+
+        let mut req = Request {
+            format: Format::Ical,
+            ..Default::default()
+        };
+        profile.apply(&mut req, |id| id == "translator" || id == "format");
+        assert!(!req.translator);
+        assert_eq!(req.format, Format::Ical);
+        assert_eq!(req.group, "351");
+
+        let json = serde_json::to_string(&profile).unwrap();
+        assert_eq!(serde_json::from_str::<Profile>(&json).unwrap(), profile);
+        assert_eq!(
+            profile.to_string(),
+            "knt/full/351 [1_под.] translator csv template=initials"
+        );
+    }
+}
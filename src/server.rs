@@ -1,14 +1,26 @@
 use crate::{
-    config,
+    alarm::{parse_reminders, Reminder},
+    bells,
+    calendar::{Combined, SummaryStyle},
+    config, crawl, db, diff, feed,
+    filter::Filter,
+    format::{self, Format},
+    freebusy,
+    merge::{self, GroupRef},
+    models::{self, ExamList, Schedule},
+    overrides::Overrides,
+    snapshot, subgroup, subscription,
     tracto::{self, find_subgroups, validate_request},
-    Config, Request,
-    models::{Schedule, ExamList},
+    webhook, Config, Request,
 };
-use icalendar::Calendar;
 
-use actix_web::{get, middleware::Logger, web};
-use serde::Deserialize;
-use std::{io::Write, path::PathBuf, process::ExitCode};
+use actix_web::{
+    delete, get, http::header, middleware::Logger, post, put, web, CustomizeResponder, HttpRequest,
+    Responder,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, fmt::Display, io::Write, path::PathBuf, process::ExitCode};
 
 #[derive(Debug, thiserror::Error)]
 enum ServerError {
@@ -17,6 +29,15 @@ enum ServerError {
 
     #[error("Bad request: {0}")]
     BadRequest(String),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("Not found: {0}")]
+    NotFound(String),
+
+    #[error("Gone: {0}")]
+    Gone(String),
 }
 
 impl actix_web::error::ResponseError for ServerError {
@@ -30,6 +51,9 @@ impl actix_web::error::ResponseError for ServerError {
         match *self {
             ServerError::InternalError { .. } => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
             ServerError::BadRequest { .. } => actix_web::http::StatusCode::BAD_REQUEST,
+            ServerError::Forbidden { .. } => actix_web::http::StatusCode::FORBIDDEN,
+            ServerError::NotFound { .. } => actix_web::http::StatusCode::NOT_FOUND,
+            ServerError::Gone { .. } => actix_web::http::StatusCode::GONE,
         }
     }
 }
@@ -40,23 +64,95 @@ impl From<std::io::Error> for ServerError {
     }
 }
 
+impl From<rusqlite::Error> for ServerError {
+    fn from(e: rusqlite::Error) -> Self {
+        Self::InternalError(e.to_string())
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct NewSubscriptionResponse {
+    url: String,
+    /// Needed to read, change or delete the subscription, shown only once
+    secret: String,
+    /// Conflicts between the chosen subgroups
+    warnings: Vec<String>,
+    #[serde(flatten)]
+    subscription: subscription::Subscription,
+}
+
+#[derive(Debug, Deserialize)]
+struct FreeBusyParams {
+    from: Option<String>,
+    to: Option<String>,
+    format: Option<Format>,
+}
+
 #[derive(Debug, Deserialize)]
 struct OptParams {
     subgroups: Option<String>,
     translator: Option<bool>,
+    stop_at_session: Option<bool>,
+    style: Option<SummaryStyle>,
+    template: Option<String>,
+    alarm: Option<String>,
+    exam_alarm: Option<String>,
+    format: Option<Format>,
 }
 
 pub async fn run_server(cfg: Config) -> ExitCode {
     let (addr, port) = (cfg.addr.clone(), cfg.port);
 
+    let refresh_cfg = cfg.clone();
+    actix_web::rt::spawn(async move {
+        let period =
+            std::time::Duration::from_secs(refresh_cfg.webhooks.refresh_minutes.max(1) * 60);
+        let mut interval = actix_web::rt::time::interval(period);
+        loop {
+            interval.tick().await;
+            webhook::refresh(&refresh_cfg).await;
+        }
+    });
+
+    if cfg.crawl.interval_hours > 0 {
+        let crawl_cfg = cfg.clone();
+        actix_web::rt::spawn(async move {
+            let period = std::time::Duration::from_secs(crawl_cfg.crawl.interval_hours * 3600);
+            let mut interval = actix_web::rt::time::interval(period);
+            loop {
+                interval.tick().await;
+                let options = crawl::CrawlOptions::from(&crawl_cfg.crawl);
+                if let Err(e) = crawl::crawl(&crawl_cfg, &options).await {
+                    log::error!("Cannot crawl: {e}");
+                }
+            }
+        });
+    }
+
     let server = actix_web::HttpServer::new(move || {
         actix_web::App::new()
             .wrap(Logger::new("%{r}a %r %s | %T sec."))
             .app_data(web::Data::new(cfg.clone()))
             .service(index_handler)
             .service(subgroups_handler)
+            .service(subgroup_categories_handler)
+            .service(unknown_values_handler)
+            .service(bells_handler)
+            .service(changes_handler)
+            .service(webhook_create_handler)
+            .service(webhook_get_handler)
+            .service(webhook_delete_handler)
+            .service(webhook_deliveries_handler)
+            .service(feed_handler)
+            .service(subscription_create_handler)
+            .service(subscription_get_handler)
+            .service(subscription_delete_handler)
+            .service(subscription_overrides_handler)
+            .service(freebusy_handler)
+            .service(subscription_cal_handler)
             .service(request_cal_handler)
             .service(request_exam_handler)
+            .service(request_all_handler)
             .service(another_request)
     })
     .bind((addr, port));
@@ -80,127 +176,668 @@ async fn index_handler(cfg: web::Data<Config>) -> String {
     format!("{} is up!", cfg.app_name)
 }
 
+#[derive(Debug, Serialize)]
+struct SubgroupsResponse {
+    subgroups: Vec<String>,
+    categories: Vec<subgroup::Category>,
+    /// Conflicts between the subgroups given in query
+    warnings: Vec<String>,
+}
+
 #[get("/subgroups/{department}/{form}/{group}")]
 async fn subgroups_handler(
     cfg: web::Data<Config>,
     path: web::Path<(String, String, String)>,
 ) -> Result<String, ServerError> {
-    let (department, form, group) = path.into_inner();
+    let schedule = group_schedule(&cfg, path.into_inner()).await?;
+    let subgroups = find_subgroups(&schedule);
+
+    Ok(serde_json::to_string(&subgroups).unwrap_or("[]".to_string()))
+}
+
+/// Subgroups with their categories, and conflicts of the subgroups given in query.
+#[get("/api/subgroups/{department}/{form}/{group}")]
+async fn subgroup_categories_handler(
+    cfg: web::Data<Config>,
+    path: web::Path<(String, String, String)>,
+    params: web::Query<OptParams>,
+    http: HttpRequest,
+) -> Result<web::Json<SubgroupsResponse>, ServerError> {
+    let schedule = group_schedule(&cfg, path.into_inner()).await?;
+    let categories = subgroup::categories(&cfg.subgroups, &schedule);
+    let selected = parse_subgroups(&params, http.query_string())?;
+    Ok(web::Json(SubgroupsResponse {
+        subgroups: find_subgroups(&schedule),
+        warnings: subgroup::conflicts(&categories, &selected),
+        categories,
+    }))
+}
+
+async fn group_schedule(
+    cfg: &Config,
+    (department, form, group): (String, String, String),
+) -> Result<Schedule, ServerError> {
     let req = Request {
         department,
         form,
         group,
-        translator: false,
-        subgroups: Vec::new(),
+        ..Default::default()
     };
+    tracto::fetch_schedule(cfg, &req)
+        .await
+        .map_err(|e| ServerError::InternalError(e.to_string()))
+}
 
-    let schedule = tracto::fetch_schedule(&cfg, &req)
+/// Stores the schedule fetched for the group and its exams as new snapshots.
+async fn record_group(cfg: &Config, req: &Request, schedule: &Schedule) -> Result<(), ServerError> {
+    snapshot::record_fetched(req, schedule).await;
+    let exams = tracto::fetch_exam(cfg, req)
         .await
         .map_err(|e| ServerError::InternalError(e.to_string()))?;
+    snapshot::record_fetched(req, &exams).await;
+    Ok(())
+}
 
-    let subgroups = find_subgroups(&schedule);
+#[get("/api/unknown")]
+async fn unknown_values_handler() -> web::Json<BTreeMap<&'static str, BTreeMap<String, u64>>> {
+    web::Json(models::unknown_values())
+}
 
-    Ok(serde_json::to_string(&subgroups).unwrap_or("[]".to_string()))
+/// Pair times of the department and lessons off them, from crawled schedules.
+#[get("/api/departments/{department}/bells")]
+async fn bells_handler(
+    path: web::Path<String>,
+) -> Result<web::Json<bells::BellsReport>, ServerError> {
+    let department = path.into_inner();
+    // Reads a snapshot of every group, so it runs off the worker thread
+    let report = web::block(move || bells::department_report(&department))
+        .await
+        .map_err(|e| ServerError::InternalError(e.to_string()))?
+        .map_err(|e| match e {
+            tracto::RequestError::Invalid(e) => ServerError::NotFound(e),
+            e => ServerError::InternalError(e.to_string()),
+        })?;
+    Ok(web::Json(report))
 }
 
-#[get("/{department}/{form}/{group}")]
-async fn request_cal_handler(
+#[derive(Debug, Deserialize)]
+struct ChangesParams {
+    /// Hours to look back
+    since: Option<u32>,
+}
+
+#[get("/api/changes/{department}/{form}/{group}")]
+async fn changes_handler(
     cfg: web::Data<Config>,
     path: web::Path<(String, String, String)>,
-    params: web::Query<OptParams>,
-) -> Result<actix_files::NamedFile, ServerError> {
+    params: web::Query<ChangesParams>,
+) -> Result<web::Json<diff::Changes>, ServerError> {
     let (department, form, group) = path.into_inner();
-    let translator = params.translator.unwrap_or(false);
-    let subgroups: Vec<String> = match &params.subgroups {
-        None => Vec::new(),
-        Some(s) => match serde_json::from_str(s.as_str()) {
-            Ok(v) => v,
-            Err(e) => return Err(
-                ServerError::BadRequest(format!("Cannot parse subroups: {}", e.to_string()))
-            )
-        },
-    };
-    let req = Request {
+    let mut req = Request {
         department,
         form,
         group,
-        translator,
-        subgroups,
+        ..Default::default()
     };
 
-    if let Err(e) = validate_request(&cfg, &req).await {
-        return Err(ServerError::BadRequest(e.to_string()));
-    };
+    let validated = validate_request(&cfg, &mut req)
+        .await
+        .map_err(|e| ServerError::BadRequest(e.to_string()))?;
 
-    let file_path = match look_up_in_cache::<Schedule>(&req) {
-        Some(file_path) => file_path,
-        None => {
-            let schedule = tracto::fetch_schedule(&cfg, &req)
-                .await
-                .map_err(|e| ServerError::InternalError(e.to_string()))?;
-            let calendar = schedule.to_ical(&cfg, &req);
-            save_to_cache::<Schedule>(&req, calendar)?
-        }
+    let since = chrono::Duration::hours(params.since.unwrap_or(24).into());
+    let changes = diff::changes_since(&req, validated.schedule, chrono::Utc::now() - since)
+        .await
+        .map_err(|e| ServerError::InternalError(e.to_string()))?;
+
+    Ok(web::Json(changes))
+}
+
+#[post("/api/webhooks")]
+async fn webhook_create_handler(
+    cfg: web::Data<Config>,
+    new: web::Json<webhook::NewWebhook>,
+    http: HttpRequest,
+) -> Result<web::Json<webhook::Webhook>, ServerError> {
+    let mut new = new.into_inner();
+    if !cfg.webhooks.may_register(&new.url, bearer_token(&http)) {
+        return Err(ServerError::Forbidden(
+            "Webhooks for this host need the admin token".into(),
+        ));
+    }
+    let url = new.url.clone();
+    web::block(move || webhook::resolve_public(&url))
+        .await
+        .map_err(|e| ServerError::InternalError(e.to_string()))?
+        .map_err(ServerError::BadRequest)?;
+
+    let mut req = Request {
+        department: new.department.clone(),
+        form: new.form.clone(),
+        group: new.group.clone(),
+        subgroups: new.subgroups.clone(),
+        ..Default::default()
     };
+    let validated = validate_request(&cfg, &mut req)
+        .await
+        .map_err(|e| ServerError::BadRequest(e.to_string()))?;
+    new.subgroups.clone_from(&req.subgroups);
+    // Make sure there are snapshots to report changes against
+    record_group(&cfg, &req, &validated.schedule).await?;
+
+    let conn = db::open()?;
+    Ok(web::Json(webhook::register(&conn, new)?))
+}
+
+#[get("/api/webhooks/{id}")]
+async fn webhook_get_handler(
+    path: web::Path<String>,
+) -> Result<web::Json<webhook::WebhookInfo>, ServerError> {
+    let conn = db::open()?;
+    match webhook::get(&conn, &path.into_inner())? {
+        Some(webhook) => Ok(web::Json(webhook.into())),
+        None => Err(ServerError::NotFound("No such webhook".into())),
+    }
+}
+
+#[delete("/api/webhooks/{id}")]
+async fn webhook_delete_handler(path: web::Path<String>) -> Result<String, ServerError> {
+    let conn = db::open()?;
+    match webhook::remove(&conn, &path.into_inner())? {
+        true => Ok("Deleted".to_string()),
+        false => Err(ServerError::NotFound("No such webhook".into())),
+    }
+}
+
+#[get("/api/webhooks/{id}/deliveries")]
+async fn webhook_deliveries_handler(
+    path: web::Path<String>,
+) -> Result<web::Json<Vec<webhook::Delivery>>, ServerError> {
+    let conn = db::open()?;
+    let deliveries = webhook::deliveries(&conn, &path.into_inner(), webhook::DELIVERIES_SHOWN)?;
+    Ok(web::Json(deliveries))
+}
+
+#[get("/feed/{department}/{form}/{group}.atom")]
+async fn feed_handler(
+    cfg: web::Data<Config>,
+    path: web::Path<(String, String, String)>,
+    params: web::Query<OptParams>,
+    http: HttpRequest,
+) -> Result<actix_web::HttpResponse, ServerError> {
+    let mut req = build_request(path.into_inner(), &params, http.query_string(), false)?;
+    if !req.with.is_empty() {
+        return Err(ServerError::BadRequest(
+            "Feeds cannot merge groups, subscribe to each one".into(),
+        ));
+    }
 
-    Ok(actix_files::NamedFile::open(file_path)?)
+    let validated = validate_request(&cfg, &mut req)
+        .await
+        .map_err(|e| ServerError::BadRequest(e.to_string()))?;
+    if let Err(e) = record_group(&cfg, &req, &validated.schedule).await {
+        log::error!("Cannot fetch group for feed: {e}");
+    }
+
+    let schedules = snapshot::load_all::<Schedule>(&req)?;
+    let exams = snapshot::load_all::<ExamList>(&req)?;
+
+    Ok(actix_web::HttpResponse::Ok()
+        .content_type("application/atom+xml; charset=utf-8")
+        .body(feed::atom(&cfg, &req, &schedules, &exams)))
+}
+
+#[get("/{department}/{form}/{group}")]
+async fn request_cal_handler(
+    cfg: web::Data<Config>,
+    path: web::Path<(String, String, String)>,
+    params: web::Query<OptParams>,
+    http: HttpRequest,
+) -> Result<CalendarFile, ServerError> {
+    let mut req = build_request(path.into_inner(), &params, http.query_string(), false)?;
+    req.format = requested_format(&http, &params);
+
+    calendar_file(&cfg, req).await
 }
 
 #[get("/exam/{department}/full/{group}")]
 async fn request_exam_handler(
     cfg: web::Data<Config>,
     path: web::Path<(String, String)>,
-) -> Result<actix_files::NamedFile, ServerError> {
+    params: web::Query<OptParams>,
+    http: HttpRequest,
+) -> Result<CalendarFile, ServerError> {
     let (department, group) = path.into_inner();
-    let form = "full";
-    let translator = false;
-    let subgroups = Vec::new();
-    let req = Request {
+    let mut req = Request {
         department,
-        form: form.to_string(),
+        form: "full".to_string(),
         group,
-        translator,
-        subgroups,
+        template: params.template.clone(),
+        exam_alarm: parse_alarm(&params.exam_alarm)?,
+        format: requested_format(&http, &params),
+        ..Default::default()
     };
 
-    if let Err(e) = validate_request(&cfg, &req).await {
+    if let Err(e) = validate_request(&cfg, &mut req).await {
         return Err(ServerError::BadRequest(e.to_string()));
     };
 
     let file_path = match look_up_in_cache::<ExamList>(&req) {
         Some(file_path) => file_path,
         None => {
-            let schedule = tracto::fetch_exam(&cfg, &req)
+            let exams = tracto::fetch_exam(&cfg, &req)
                 .await
                 .map_err(|e| ServerError::InternalError(e.to_string()))?;
-            let calendar = schedule.to_ical();
-            save_to_cache::<ExamList>(&req, calendar)?
+            save_to_cache::<ExamList>(&req, format::render(&exams, &cfg, &req))?
         }
     };
 
-    Ok(actix_files::NamedFile::open(file_path)?)
+    open_cached(file_path, req.format)
+}
+
+#[get("/all/{department}/{form}/{group}")]
+async fn request_all_handler(
+    cfg: web::Data<Config>,
+    path: web::Path<(String, String, String)>,
+    params: web::Query<OptParams>,
+    http: HttpRequest,
+) -> Result<CalendarFile, ServerError> {
+    let mut req = build_request(path.into_inner(), &params, http.query_string(), true)?;
+    req.format = requested_format(&http, &params);
+
+    calendar_file(&cfg, req).await
+}
+
+#[post("/api/subscriptions")]
+async fn subscription_create_handler(
+    cfg: web::Data<Config>,
+    new: web::Json<subscription::NewSubscription>,
+    http: HttpRequest,
+) -> Result<web::Json<NewSubscriptionResponse>, ServerError> {
+    let mut new = new.into_inner();
+    new.overrides.validate().map_err(ServerError::BadRequest)?;
+    new.overrides
+        .assign_ids()
+        .map_err(ServerError::BadRequest)?;
+    let mut req = new.params.request();
+    let warnings = validate_request(&cfg, &mut req)
+        .await
+        .map_err(|e| ServerError::BadRequest(e.to_string()))?
+        .warnings;
+    new.params.subgroups = req.subgroups;
+
+    let conn = db::open()?;
+    let (subscription, secret) = subscription::create(&conn, new, chrono::Utc::now().timestamp())?;
+    let info = http.connection_info();
+    Ok(web::Json(NewSubscriptionResponse {
+        url: format!(
+            "{}://{}/s/{}.ics",
+            info.scheme(),
+            info.host(),
+            subscription.token
+        ),
+        secret,
+        warnings,
+        subscription,
+    }))
+}
+
+/// Value of `Authorization: Bearer <token>`.
+fn bearer_token(http: &HttpRequest) -> Option<&str> {
+    http.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Opens the database if the request has the secret of the subscription.
+fn manage_subscription(
+    token: &str,
+    http: &HttpRequest,
+) -> Result<rusqlite::Connection, ServerError> {
+    let conn = db::open()?;
+    let secret = bearer_token(http).unwrap_or_default();
+    if !subscription::is_managed_by(&conn, token, secret)? {
+        return Err(ServerError::Forbidden(
+            "Unknown subscription or wrong secret".into(),
+        ));
+    }
+    Ok(conn)
+}
+
+#[get("/api/subscriptions/{token}")]
+async fn subscription_get_handler(
+    path: web::Path<String>,
+    http: HttpRequest,
+) -> Result<web::Json<subscription::Subscription>, ServerError> {
+    let token = path.into_inner();
+    let conn = manage_subscription(&token, &http)?;
+    match subscription::get(&conn, &token)? {
+        Some(subscription) => Ok(web::Json(subscription)),
+        None => Err(ServerError::NotFound("No such subscription".into())),
+    }
+}
+
+#[delete("/api/subscriptions/{token}")]
+async fn subscription_delete_handler(
+    path: web::Path<String>,
+    http: HttpRequest,
+) -> Result<String, ServerError> {
+    let token = path.into_inner();
+    let conn = manage_subscription(&token, &http)?;
+    match subscription::remove(&conn, &token)? {
+        true => Ok("Deleted".to_string()),
+        false => Err(ServerError::NotFound("No such subscription".into())),
+    }
+}
+
+#[put("/api/subscriptions/{token}/overrides")]
+async fn subscription_overrides_handler(
+    path: web::Path<String>,
+    overrides: web::Json<Overrides>,
+    http: HttpRequest,
+) -> Result<web::Json<subscription::Subscription>, ServerError> {
+    let token = path.into_inner();
+    let conn = manage_subscription(&token, &http)?;
+    let mut overrides = overrides.into_inner();
+    overrides.validate().map_err(ServerError::BadRequest)?;
+    overrides.assign_ids().map_err(ServerError::BadRequest)?;
+
+    if !subscription::set_overrides(&conn, &token, &overrides)? {
+        return Err(ServerError::NotFound("No such subscription".into()));
+    }
+    match subscription::get(&conn, &token)? {
+        Some(subscription) => Ok(web::Json(subscription)),
+        None => Err(ServerError::NotFound("No such subscription".into())),
+    }
+}
+
+/// Common free pairs of repeated `group` and `teacher` parameters,
+/// as JSON or as VFREEBUSY with `format=ical`.
+#[get("/api/freebusy")]
+async fn freebusy_handler(
+    cfg: web::Data<Config>,
+    params: web::Query<FreeBusyParams>,
+    http: HttpRequest,
+) -> Result<actix_web::HttpResponse, ServerError> {
+    let date = |param: &Option<String>| -> Result<Option<chrono::NaiveDate>, ServerError> {
+        param
+            .as_deref()
+            .map(|s| chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d"))
+            .transpose()
+            .map_err(|e| ServerError::BadRequest(format!("Incorrect date, use YYYY-MM-DD: {e}")))
+    };
+    let query = freebusy::Query::new(
+        parse_repeated(http.query_string(), "group")?,
+        parse_repeated(http.query_string(), "teacher")?,
+        date(&params.from)?,
+        date(&params.to)?,
+        chrono::Utc::now()
+            .with_timezone(&chrono_tz::Europe::Saratov)
+            .date_naive(),
+    )
+    .map_err(ServerError::BadRequest)?;
+
+    let attendees = freebusy::attendees(&cfg, &query)
+        .await
+        .map_err(|e| match e {
+            tracto::RequestError::Invalid(e) => ServerError::BadRequest(e),
+            e => ServerError::InternalError(e.to_string()),
+        })?;
+    let free_busy = freebusy::free_busy(&cfg, &attendees, query.from, query.to);
+
+    match params.format.unwrap_or(Format::Json) {
+        Format::Json => Ok(actix_web::HttpResponse::Ok().json(free_busy)),
+        Format::Ical => Ok(actix_web::HttpResponse::Ok()
+            .content_type(Format::Ical.content_type())
+            .body(freebusy::vfreebusy(
+                &free_busy,
+                query.from,
+                query.to,
+                chrono::Utc::now(),
+            ))),
+        _ => Err(ServerError::BadRequest(
+            "Free/busy is available as json or ical".into(),
+        )),
+    }
+}
+
+#[get("/s/{token}.ics")]
+async fn subscription_cal_handler(
+    cfg: web::Data<Config>,
+    path: web::Path<String>,
+) -> Result<CalendarFile, ServerError> {
+    let now = chrono::Utc::now().timestamp();
+    match subscription::follow(&db::open()?, &path.into_inner(), now)? {
+        subscription::Lookup::Found(subscription) => {
+            calendar_file(&cfg, subscription.request()).await
+        }
+        subscription::Lookup::Expired => Err(ServerError::Gone("Subscription expired".into())),
+        subscription::Lookup::Missing => Err(ServerError::NotFound("No such subscription".into())),
+    }
 }
 
 #[get("/{tail:.*}")]
 async fn another_request(path: web::Path<String>) -> String {
     let tail = path.into_inner();
     log::error!("Another request {}", tail);
-    format!("Aboba")
+    "Aboba".to_string()
+}
+
+fn build_request(
+    (department, form, group): (String, String, String),
+    params: &OptParams,
+    query: &str,
+    exams: bool,
+) -> Result<Request, ServerError> {
+    Ok(Request {
+        department,
+        form,
+        group,
+        subgroups: parse_subgroups(params, query)?,
+        filters: parse_filters(query)?,
+        with: parse_groups(query)?,
+        translator: params.translator.unwrap_or(false),
+        style: params.style.unwrap_or_default(),
+        template: params.template.clone(),
+        alarm: parse_alarm(&params.alarm)?,
+        exam_alarm: parse_alarm(&params.exam_alarm)?,
+        exams,
+        stop_at_session: exams && params.stop_at_session.unwrap_or(false),
+        format: params.format.unwrap_or_default(),
+        profile: None,
+        overrides: Overrides::default(),
+    })
+}
+
+/// Subgroups given as a JSON array, as a comma-separated list
+/// or as repeated `subgroup` parameters.
+fn parse_subgroups(params: &OptParams, query: &str) -> Result<Vec<String>, ServerError> {
+    let mut subgroups: Vec<String> = match params.subgroups.as_deref().map(str::trim) {
+        None | Some("") => Vec::new(),
+        Some(s) if s.starts_with('[') => serde_json::from_str(s)
+            .map_err(|e| ServerError::BadRequest(format!("Cannot parse subgroups: {e}")))?,
+        Some(s) => s.split(',').map(str::to_string).collect(),
+    };
+
+    let pairs = web::Query::<Vec<(String, String)>>::from_query(query)
+        .map_err(|e| ServerError::BadRequest(e.to_string()))?;
+    subgroups.extend(
+        pairs
+            .into_inner()
+            .into_iter()
+            .filter(|(key, _)| key == "subgroup")
+            .map(|(_, value)| value),
+    );
+
+    Ok(subgroups
+        .into_iter()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect())
+}
+
+/// Values of a repeated parameter.
+fn parse_repeated<T>(query: &str, name: &str) -> Result<Vec<T>, ServerError>
+where
+    T: std::str::FromStr,
+    T::Err: Display,
+{
+    web::Query::<Vec<(String, String)>>::from_query(query)
+        .map_err(|e| ServerError::BadRequest(e.to_string()))?
+        .into_inner()
+        .into_iter()
+        .filter(|(key, _)| key == name)
+        .map(|(_, value)| {
+            value
+                .parse()
+                .map_err(|e| ServerError::BadRequest(format!("Incorrect {name}: {e}")))
+        })
+        .collect()
+}
+
+/// Repeated `filter` parameters.
+fn parse_filters(query: &str) -> Result<Vec<Filter>, ServerError> {
+    parse_repeated(query, "filter")
+}
+
+/// Groups merged into the calendar, repeated `with` parameters.
+fn parse_groups(query: &str) -> Result<Vec<GroupRef>, ServerError> {
+    parse_repeated(query, "with")
+}
+
+/// `format` parameter, otherwise the `Accept` header.
+fn requested_format(http: &HttpRequest, params: &OptParams) -> Format {
+    params
+        .format
+        .or_else(|| {
+            let accept = http.headers().get(actix_web::http::header::ACCEPT)?;
+            Format::from_accept(accept.to_str().ok()?)
+        })
+        .unwrap_or_default()
+}
+
+/// Cached calendar, its body depends on the `Accept` header.
+type CalendarFile = CustomizeResponder<actix_files::NamedFile>;
+
+fn open_cached(file_path: PathBuf, format: Format) -> Result<CalendarFile, ServerError> {
+    let mime = format
+        .content_type()
+        .parse()
+        .map_err(|e: mime::FromStrError| ServerError::InternalError(e.to_string()))?;
+    Ok(actix_files::NamedFile::open(file_path)?
+        .set_content_type(mime)
+        .customize()
+        .insert_header((header::VARY, "Accept")))
+}
+
+/// Header with a warning about the request, percent-encoded
+/// since subgroup names are not ASCII.
+const WARNING_HEADER: &str = "X-Calar-Warning";
+
+fn percent_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b' '..=b'~' if b != b'%' => char::from(b).to_string(),
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// Lessons (and exams, if requested) calendar file, fetched
+/// unless it is cached already.
+async fn calendar_file(cfg: &Config, mut req: Request) -> Result<CalendarFile, ServerError> {
+    let validated = validate_request(cfg, &mut req)
+        .await
+        .map_err(|e| ServerError::BadRequest(e.to_string()))?;
+    let req = &req;
+
+    let cached = if req.exams {
+        look_up_in_cache::<Combined>(req)
+    } else {
+        look_up_in_cache::<Schedule>(req)
+    };
+    let file_path = match cached {
+        Some(file_path) => file_path,
+        None => {
+            let schedule = merge::from_fetched(cfg, req, validated.schedule, validated.with);
+            if req.exams {
+                let exams = merge::exams(cfg, req)
+                    .await
+                    .map_err(|e| ServerError::InternalError(e.to_string()))?;
+                let combined = Combined { schedule, exams };
+                save_to_cache::<Combined>(req, format::render(&combined, cfg, req))?
+            } else {
+                save_to_cache::<Schedule>(req, format::render(&schedule, cfg, req))?
+            }
+        }
+    };
+
+    let mut file = open_cached(file_path, req.format)?;
+    for warning in &validated.warnings {
+        file = file.append_header((WARNING_HEADER, percent_encode(warning)));
+    }
+    Ok(file)
+}
+
+fn parse_alarm(param: &Option<String>) -> Result<Vec<Reminder>, ServerError> {
+    match param {
+        None => Ok(Vec::new()),
+        Some(s) => parse_reminders(s).map_err(ServerError::BadRequest),
+    }
 }
 
 pub fn gen_filename<T>(req: &Request) -> String {
     let tmp_vec: Vec<&str> = std::any::type_name::<T>().split("::").collect();
+    let overrides = match req.overrides.is_empty() {
+        true => Vec::new(),
+        false => vec![serde_json::to_string(&req.overrides).unwrap_or_default()],
+    };
     format!(
-        "{}-{}-{}-{}-{}{}.ics",
+        "{}-{}-{}-{}-{}{}{}{}{}{}{}{}{}{}.{}",
         tmp_vec[tmp_vec.len() - 1],
         req.department,
         req.form,
         req.group,
         req.subgroups.join("_"),
-        if req.translator { "-t" } else { "" }
+        if req.translator { "-t" } else { "" },
+        if req.stop_at_session { "-s" } else { "" },
+        match req.style {
+            SummaryStyle::Short => "",
+            SummaryStyle::Full => "-full",
+            SummaryStyle::None => "-none",
+        },
+        req.template
+            .as_ref()
+            .map(|name| format!("-tpl-{name}"))
+            .unwrap_or_default(),
+        reminders_suffix("a", &req.alarm),
+        reminders_suffix("ea", &req.exam_alarm),
+        digest_suffix("f", &req.filters),
+        digest_suffix("w", &req.with),
+        digest_suffix("o", &overrides),
+        req.format.extension()
     )
 }
 
+/// Filters and groups may contain any characters, so file names get their digest.
+fn digest_suffix(prefix: &str, items: &[impl Display]) -> String {
+    if items.is_empty() {
+        return String::new();
+    }
+    let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+    let digest = Sha256::digest(items.join("\n").as_bytes());
+    let digest: String = digest[..8].iter().map(|b| format!("{b:02x}")).collect();
+    format!("-{prefix}{digest}")
+}
+
+fn reminders_suffix(prefix: &str, reminders: &[Reminder]) -> String {
+    if reminders.is_empty() {
+        return String::new();
+    }
+    let reminders: Vec<String> = reminders.iter().map(|r| r.to_string()).collect();
+    format!("-{prefix}{}", reminders.join("_"))
+}
+
 fn get_cache_dir() -> PathBuf {
     let proj_dirs =
         directories::ProjectDirs::from(config::QUALIFIER, config::ORG_NAME, config::APP_NAME)
@@ -209,13 +846,13 @@ fn get_cache_dir() -> PathBuf {
     proj_dirs.cache_dir().join("calendars")
 }
 
-fn save_to_cache<T>(req: &Request, calendar: Calendar) -> Result<PathBuf, ServerError> {
+pub fn save_to_cache<T>(req: &Request, contents: impl Display) -> std::io::Result<PathBuf> {
     let cache_dir = get_cache_dir();
     std::fs::create_dir_all(cache_dir.clone())?;
     let file_path = cache_dir.join(gen_filename::<T>(req));
 
     let mut file = std::fs::File::create(file_path.clone())?;
-    file.write_all(calendar.to_string().as_bytes())?;
+    file.write_all(contents.to_string().as_bytes())?;
 
     Ok(file_path)
 }
@@ -0,0 +1,266 @@
+use crate::{
+    calendar::{week_type, Combined},
+    db::Stored,
+    merge,
+    models::{ExamList, Schedule, WeekType},
+    occurrence::{Kind, Occurrence},
+    snapshot,
+    tracto::RequestResult,
+    Config, Request,
+};
+
+use chrono::{prelude::*, Duration};
+use chrono_tz::{Europe::Saratov, Tz};
+use clap::Parser;
+use std::fmt::Write;
+
+#[derive(Parser, Debug)]
+pub struct ShowArgs {
+    #[command(flatten)]
+    pub request: Request,
+    /// Show lessons of today, the default
+    #[arg(long, conflicts_with_all = ["week", "next"])]
+    pub today: bool,
+    /// Show lessons of the current week
+    #[arg(long, conflicts_with = "next")]
+    pub week: bool,
+    /// Show the current or the next lesson
+    #[arg(long)]
+    pub next: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Today,
+    Week,
+    Next,
+}
+
+impl ShowArgs {
+    pub fn period(&self) -> Period {
+        if self.week {
+            Period::Week
+        } else if self.next {
+            Period::Next
+        } else {
+            Period::Today
+        }
+    }
+}
+
+/// Fetched data, or the latest snapshot if Tracto is unavailable.
+/// Snapshots are per group, so merged requests have no fallback.
+fn or_saved<T: Stored>(req: &Request, fetched: RequestResult<T>) -> RequestResult<T> {
+    if !req.with.is_empty() {
+        return fetched;
+    }
+    fetched.or_else(|e| match snapshot::load_latest::<T>(req) {
+        Ok(Some(snapshot)) => {
+            log::warn!(
+                "Cannot fetch, showing data saved at {}: {e}",
+                snapshot
+                    .taken_at
+                    .with_timezone(&Saratov)
+                    .format("%d.%m %H:%M")
+            );
+            Ok(snapshot.data)
+        }
+        _ => Err(e),
+    })
+}
+
+/// Occurrences of the request, filtered like in calendar.
+pub async fn occurrences(cfg: &Config, req: &Request) -> RequestResult<Vec<Occurrence>> {
+    let schedule = or_saved::<Schedule>(req, merge::schedule(cfg, req).await)?;
+    if !req.exams {
+        return Ok(schedule.occurrences(cfg, req));
+    }
+
+    let exams = or_saved::<ExamList>(req, merge::exams(cfg, req).await)?;
+    Ok(Combined { schedule, exams }.occurrences(cfg, req))
+}
+
+fn select(occurrences: &[Occurrence], period: Period, now: DateTime<Tz>) -> Vec<&Occurrence> {
+    let today = now.date_naive();
+    match period {
+        Period::Today => occurrences
+            .iter()
+            .filter(|o| o.start.date_naive() == today)
+            .collect(),
+        Period::Week => {
+            let monday = today - Duration::days(today.weekday().num_days_from_monday().into());
+            let next_monday = monday + Duration::weeks(1);
+            occurrences
+                .iter()
+                .filter(|o| (monday..next_monday).contains(&o.start.date_naive()))
+                .collect()
+        }
+        Period::Next => occurrences
+            .iter()
+            .find(|o| o.end > now)
+            .into_iter()
+            .collect(),
+    }
+}
+
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const GREEN: &str = "\x1b[1;32m";
+const RED: &str = "\x1b[31m";
+const CYAN: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+pub const WEEKDAYS: [&str; 7] = [
+    "Понедельник",
+    "Вторник",
+    "Среда",
+    "Четверг",
+    "Пятница",
+    "Суббота",
+    "Воскресенье",
+];
+
+fn pad(s: &str, width: usize) -> String {
+    let len = s.chars().count();
+    format!("{s}{}", " ".repeat(width.saturating_sub(len)))
+}
+
+fn week_name(date: NaiveDate) -> &'static str {
+    match week_type(date) {
+        WeekType::Nom => "числитель",
+        _ => "знаменатель",
+    }
+}
+
+/// Lessons of `period` as a table, grouped by day. Past lessons are dimmed
+/// and the current one is highlighted when `color` is set.
+pub fn table(occurrences: &[Occurrence], period: Period, now: DateTime<Tz>, color: bool) -> String {
+    let paint = |style: &'static str| if color { style } else { "" };
+    let reset = paint(RESET);
+
+    let selected = select(occurrences, period, now);
+    if selected.is_empty() {
+        return match period {
+            Period::Today => "Сегодня занятий нет\n",
+            Period::Week => "На этой неделе занятий нет\n",
+            Period::Next => "Занятий больше нет\n",
+        }
+        .to_string();
+    }
+
+    let summary_width = selected
+        .iter()
+        .map(|o| o.summary.chars().count())
+        .max()
+        .unwrap_or_default();
+
+    let mut out = String::new();
+    let mut day = None;
+    for occurrence in selected {
+        let date = occurrence.start.date_naive();
+        if day != Some(date) {
+            if day.is_some() {
+                out.push('\n');
+            }
+            day = Some(date);
+            let weekday = WEEKDAYS[date.weekday().num_days_from_monday() as usize];
+            writeln!(
+                out,
+                "{}{weekday}, {} ({}){reset}",
+                paint(BOLD),
+                date.format("%d.%m"),
+                week_name(date)
+            )
+            .unwrap();
+        }
+
+        let style = if occurrence.end <= now {
+            paint(DIM)
+        } else if occurrence.start <= now {
+            paint(GREEN)
+        } else if occurrence.kind == Kind::Exam {
+            paint(RED)
+        } else {
+            ""
+        };
+        writeln!(
+            out,
+            "{}{}–{}{reset}  {style}{}  {}{reset}",
+            paint(CYAN),
+            occurrence.start.format("%H:%M"),
+            occurrence.end.format("%H:%M"),
+            pad(&occurrence.summary, summary_width),
+            occurrence.location
+        )
+        .unwrap();
+    }
+    out
+}
+
+pub fn now() -> DateTime<Tz> {
+    Utc::now().with_timezone(&Saratov)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::*;
+
+    fn occurrences() -> Vec<Occurrence> {
+        let schedule = schedule(vec![
+            lesson_json(1, 1, "FULL", ""),
+            lesson_json(2, 1, "NOM", ""),
+            lesson_json(3, 1, "DENOM", ""),
+            lesson_json(4, 3, "FULL", "2_под."),
+        ]);
+        let request = Request {
+            subgroups: vec!["1_под.".to_string()],
+            ..Default::default()
+        };
+        schedule.occurrences(&Config::default(), &request)
+    }
+
+    #[test]
+    fn today_resolves_week_type() {
+        let occurrences = occurrences();
+        let first = occurrences[0].start;
+        let first_day = first.date_naive();
+
+        let table = table(&occurrences, Period::Today, first, false);
+        assert!(table.starts_with(&format!(
+            "{}, {} ({})\n",
+            WEEKDAYS[first_day.weekday().num_days_from_monday() as usize],
+            first_day.format("%d.%m"),
+            week_name(first_day)
+        )));
+        let expected = match week_type(first_day) {
+            WeekType::Nom => "Предмет 2",
+            _ => "Предмет 3",
+        };
+        assert!(table.contains(expected));
+        assert_eq!(table.lines().count(), 3);
+    }
+
+    #[test]
+    fn week_honors_subgroups() {
+        let occurrences = occurrences();
+        let table = table(&occurrences, Period::Week, occurrences[0].start, false);
+        assert!(table.contains("Предмет 1"));
+        assert!(!table.contains("Предмет 4"));
+    }
+
+    #[test]
+    fn next_is_current_or_upcoming() {
+        let occurrences = occurrences();
+        let during = occurrences[0].start + Duration::minutes(10);
+        let table = table(&occurrences, Period::Next, during, true);
+        assert_eq!(table.lines().count(), 2);
+        assert!(table.contains(GREEN));
+
+        let after = occurrences.last().unwrap().end;
+        assert_eq!(
+            super::table(&occurrences, Period::Next, after, false),
+            "Занятий больше нет\n"
+        );
+    }
+}
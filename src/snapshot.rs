@@ -0,0 +1,89 @@
+use crate::{
+    db::{self, GroupKey, Stored},
+    Request,
+};
+
+use chrono::{DateTime, TimeZone, Utc};
+use std::io;
+
+/// Fetched Tracto data stored at some moment.
+pub struct Snapshot<T> {
+    pub taken_at: DateTime<Utc>,
+    pub data: T,
+}
+
+/// Snapshots are kept per group. Subgroups and other request options
+/// don't matter here, snapshots hold everything Tracto returned.
+fn group_key(req: &Request) -> GroupKey<'_> {
+    GroupKey {
+        department: &req.department,
+        form: &req.form,
+        group: &req.group,
+    }
+}
+
+fn db_error(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+/// Stores `data` unless it is equal to the latest snapshot.
+/// Returns whether a new snapshot was written.
+pub fn record<T: Stored>(req: &Request, data: &T) -> io::Result<bool> {
+    let mut conn = db::open().map_err(db_error)?;
+    db::record(&mut conn, &group_key(req), data, Utc::now().timestamp()).map_err(db_error)
+}
+
+/// Records freshly fetched data like `record`, on a blocking thread
+/// so that the async executor is not held up. Failures are only logged.
+pub async fn record_fetched<T: Stored + Clone + Send + 'static>(req: &Request, data: &T) {
+    let group = Request {
+        department: req.department.clone(),
+        form: req.form.clone(),
+        group: req.group.clone(),
+        ..Default::default()
+    };
+    let data = data.clone();
+    let name = format!("{}/{}/{}", group.department, group.form, group.group);
+    let recorded = actix_web::rt::task::spawn_blocking(move || record(&group, &data)).await;
+    match recorded {
+        Ok(Ok(true)) => log::info!("New {} snapshot of {name}", T::KIND),
+        Ok(Ok(false)) => {}
+        Ok(Err(e)) => log::error!("Cannot record snapshot of {name}: {e}"),
+        Err(e) => log::error!("Cannot record snapshot of {name}: {e}"),
+    }
+}
+
+/// All snapshots of a group, oldest first.
+pub fn load_all<T: Stored>(req: &Request) -> io::Result<Vec<Snapshot<T>>> {
+    let conn = db::open().map_err(db_error)?;
+    let snapshots = db::load_snapshots::<T>(&conn, &group_key(req)).map_err(db_error)?;
+
+    Ok(snapshots
+        .into_iter()
+        .filter_map(|(timestamp, data)| {
+            let taken_at = Utc.timestamp_opt(timestamp, 0).single()?;
+            Some(Snapshot { taken_at, data })
+        })
+        .collect())
+}
+
+/// The newest snapshot taken before `moment`, or the oldest one
+/// if all of them are newer.
+pub fn load_before<T: Stored>(
+    req: &Request,
+    moment: DateTime<Utc>,
+) -> io::Result<Option<Snapshot<T>>> {
+    let conn = db::open().map_err(db_error)?;
+    let snapshot = db::load_snapshot_before::<T>(&conn, &group_key(req), moment.timestamp())
+        .map_err(db_error)?;
+
+    Ok(snapshot.and_then(|(timestamp, data)| {
+        let taken_at = Utc.timestamp_opt(timestamp, 0).single()?;
+        Some(Snapshot { taken_at, data })
+    }))
+}
+
+/// The latest snapshot of a group.
+pub fn load_latest<T: Stored>(req: &Request) -> io::Result<Option<Snapshot<T>>> {
+    load_before(req, DateTime::<Utc>::MAX_UTC)
+}
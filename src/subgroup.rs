@@ -0,0 +1,248 @@
+use crate::{
+    models::{Lesson, Schedule, WeekType},
+    tracto::find_subgroups,
+};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SubgroupsConfig {
+    /// Categories set by hand, for schedules where inference goes wrong
+    pub categories: Vec<CategoryOverride>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CategoryOverride {
+    pub name: String,
+    /// Matched regardless of case and whitespace
+    pub subgroups: Vec<String>,
+    #[serde(default = "exclusive_by_default")]
+    pub exclusive: bool,
+}
+
+fn exclusive_by_default() -> bool {
+    true
+}
+
+/// Subgroups a student picks from, like "1_под." and "2_под.".
+/// A student is in at most one subgroup of an exclusive category.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Category {
+    pub name: String,
+    pub exclusive: bool,
+    pub subgroups: Vec<String>,
+}
+
+/// Subgroup names are compared regardless of case and whitespace.
+pub fn key(name: &str) -> String {
+    name.split_whitespace().collect::<String>().to_lowercase()
+}
+
+/// Name without numbers, "анг.ст.3" becomes "анг.ст".
+fn stem(name: &str) -> String {
+    name.chars()
+        .filter(|c| !c.is_ascii_digit())
+        .collect::<String>()
+        .trim_matches(|c: char| c == '_' || c == '.' || c.is_whitespace())
+        .to_string()
+}
+
+/// Whether the lessons take place at the same time on some week.
+fn parallel(a: &Lesson, b: &Lesson) -> bool {
+    let weeks_differ = matches!(
+        (&a.week_type, &b.week_type),
+        (WeekType::Nom, WeekType::Denom) | (WeekType::Denom, WeekType::Nom)
+    );
+    a.day.day_number == b.day.day_number
+        && a.lesson_time.lesson_number == b.lesson_time.lesson_number
+        && !weeks_differ
+}
+
+/// Root of `i` in the union-find `parents`.
+fn root(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+fn join(parents: &mut [usize], a: usize, b: usize) {
+    let (a, b) = (root(parents, a), root(parents, b));
+    parents[a.max(b)] = a.min(b);
+}
+
+/// Groups subgroups of the schedule into categories. Numbered subgroups
+/// sharing a name and subgroups having lessons at the same time are
+/// alternatives; configured categories take precedence.
+pub fn categories(cfg: &SubgroupsConfig, schedule: &Schedule) -> Vec<Category> {
+    let mut names = find_subgroups(schedule);
+    let mut categories = Vec::new();
+
+    for category in &cfg.categories {
+        let keys: Vec<String> = category.subgroups.iter().map(|name| key(name)).collect();
+        let (members, rest) = names
+            .into_iter()
+            .partition::<Vec<_>, _>(|name| keys.contains(&key(name)));
+        names = rest;
+        if !members.is_empty() {
+            categories.push(Category {
+                name: category.name.clone(),
+                exclusive: category.exclusive,
+                subgroups: members,
+            });
+        }
+    }
+
+    let mut parents: Vec<usize> = (0..names.len()).collect();
+    let stems: Vec<String> = names.iter().map(|name| stem(name)).collect();
+    for i in 0..names.len() {
+        for j in i + 1..names.len() {
+            let numbered = names[i].chars().any(|c| c.is_ascii_digit())
+                && names[j].chars().any(|c| c.is_ascii_digit());
+            if numbered && stems[i] == stems[j] {
+                join(&mut parents, i, j);
+            }
+        }
+    }
+
+    let index = |sub_group: &str| names.iter().position(|name| name == sub_group.trim());
+    for (n, a) in schedule.lessons.iter().enumerate() {
+        for b in &schedule.lessons[n + 1..] {
+            if let (Some(i), Some(j)) = (index(&a.sub_group), index(&b.sub_group)) {
+                if i != j && parallel(a, b) {
+                    join(&mut parents, i, j);
+                }
+            }
+        }
+    }
+
+    let roots: Vec<usize> = (0..names.len()).map(|i| root(&mut parents, i)).collect();
+    for (i, stem) in stems.into_iter().enumerate() {
+        if roots[i] != i {
+            continue;
+        }
+        let subgroups: Vec<String> = names
+            .iter()
+            .zip(&roots)
+            .filter(|(_, &r)| r == i)
+            .map(|(name, _)| name.clone())
+            .collect();
+        categories.push(Category {
+            name: stem,
+            exclusive: subgroups.len() > 1,
+            subgroups,
+        });
+    }
+    categories
+}
+
+/// Warnings about picking several subgroups of an exclusive category.
+pub fn conflicts(categories: &[Category], selected: &[String]) -> Vec<String> {
+    categories
+        .iter()
+        .filter(|category| category.exclusive)
+        .filter_map(|category| {
+            let picked: Vec<&str> = category
+                .subgroups
+                .iter()
+                .filter(|name| selected.iter().any(|s| key(s) == key(name)))
+                .map(String::as_str)
+                .collect();
+            (picked.len() > 1).then(|| {
+                format!(
+                    "Subgroups {} are alternatives of \"{}\", usually only one is needed",
+                    picked.join(", "),
+                    category.name
+                )
+            })
+        })
+        .collect()
+}
+
+/// Ways to pick one subgroup of every exclusive category, in order,
+/// at most `limit` of them.
+pub fn combinations(categories: &[Category], limit: usize) -> Vec<Vec<String>> {
+    let exclusive: Vec<&Category> = categories.iter().filter(|c| c.exclusive).collect();
+    if exclusive.is_empty() {
+        return Vec::new();
+    }
+
+    let mut combinations = vec![Vec::new()];
+    for category in exclusive {
+        combinations = combinations
+            .iter()
+            .flat_map(|picked: &Vec<String>| {
+                category.subgroups.iter().map(move |name| {
+                    let mut picked = picked.clone();
+                    picked.push(name.clone());
+                    picked
+                })
+            })
+            .take(limit)
+            .collect();
+    }
+    combinations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::*;
+
+    #[test]
+    fn categories_from_names_and_parallel_lessons() {
+        let mut english = lesson_json(5, 2, "FULL", "анг.ст.1");
+        english["lessonTime"]["lessonNumber"] = 2.into();
+        let schedule = schedule(vec![
+            lesson_json(1, 1, "FULL", "1_под."),
+            lesson_json(2, 1, "DENOM", "2_под."),
+            lesson_json(3, 3, "NOM", "немецкий"),
+            lesson_json(4, 3, "FULL", "анг.ст.3"),
+            english,
+            lesson_json(6, 4, "FULL", "цифровая_кафедра"),
+        ]);
+
+        let categories = categories(&SubgroupsConfig::default(), &schedule);
+        let category = |name: &str| categories.iter().find(|c| c.name == name).unwrap();
+        assert_eq!(category("под").subgroups, ["1_под.", "2_под."]);
+        assert!(category("под").exclusive);
+        assert_eq!(
+            category("анг.ст").subgroups,
+            ["анг.ст.1", "анг.ст.3", "немецкий"]
+        );
+        assert_eq!(
+            category("цифровая_кафедра"),
+            &Category {
+                name: "цифровая_кафедра".to_string(),
+                exclusive: false,
+                subgroups: vec!["цифровая_кафедра".to_string()],
+            }
+        );
+
+        let cfg = SubgroupsConfig {
+            categories: vec![CategoryOverride {
+                name: "Языки".to_string(),
+                subgroups: vec!["Немецкий".to_string(), "анг.ст.3".to_string()],
+                exclusive: true,
+            }],
+        };
+        let categories = super::categories(&cfg, &schedule);
+        assert_eq!(categories[0].subgroups, ["анг.ст.3", "немецкий"]);
+        assert!(categories.iter().any(|c| c.subgroups == ["анг.ст.1"]));
+
+        let selected = ["1_под.".to_string(), "2_ПОД.".to_string()];
+        assert_eq!(
+            conflicts(&categories, &selected),
+            ["Subgroups 1_под., 2_под. are alternatives of \"под\", usually only one is needed"]
+        );
+        assert!(conflicts(&categories, &selected[..1]).is_empty());
+
+        let picks = combinations(&categories, 10);
+        assert_eq!(picks.len(), 4);
+        assert_eq!(picks[0], ["анг.ст.3", "1_под."]);
+        assert_eq!(picks[3], ["немецкий", "2_под."]);
+        assert_eq!(combinations(&categories, 3).len(), 3);
+    }
+}
@@ -0,0 +1,317 @@
+use crate::{
+    alarm::Reminder, calendar::SummaryStyle, filter::Filter, merge::GroupRef, overrides::Overrides,
+    Request,
+};
+
+use rand::{distributions::Alphanumeric, Rng};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+const TOKEN_LEN: usize = 8;
+/// Length of the secret needed to manage a subscription. The token is
+/// public, it is a part of the calendar URL.
+const SECRET_LEN: usize = 32;
+
+/// Calendar options kept behind a short `/s/{token}.ics` URL.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SubscriptionParams {
+    pub department: String,
+    pub form: String,
+    pub group: String,
+    #[serde(default)]
+    pub subgroups: Vec<String>,
+    #[serde(default)]
+    pub translator: bool,
+    #[serde(default)]
+    pub filters: Vec<Filter>,
+    #[serde(default)]
+    pub with: Vec<GroupRef>,
+    #[serde(default)]
+    pub style: SummaryStyle,
+    #[serde(default)]
+    pub template: Option<String>,
+    #[serde(default)]
+    pub alarm: Vec<Reminder>,
+    #[serde(default)]
+    pub exam_alarm: Vec<Reminder>,
+    #[serde(default)]
+    pub exams: bool,
+    #[serde(default)]
+    pub stop_at_session: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewSubscription {
+    #[serde(flatten)]
+    pub params: SubscriptionParams,
+    /// Subscription stops working after this many days
+    pub expires_in_days: Option<u32>,
+    #[serde(default)]
+    pub overrides: Overrides,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Subscription {
+    pub token: String,
+    #[serde(flatten)]
+    pub params: SubscriptionParams,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub hits: u64,
+    pub last_used_at: Option<i64>,
+    pub overrides: Overrides,
+}
+
+/// Result of following a subscription URL.
+#[derive(Debug, PartialEq)]
+pub enum Lookup {
+    Found(Box<Subscription>),
+    Expired,
+    Missing,
+}
+
+impl SubscriptionParams {
+    pub fn request(&self) -> Request {
+        Request {
+            department: self.department.clone(),
+            form: self.form.clone(),
+            group: self.group.clone(),
+            subgroups: self.subgroups.clone(),
+            translator: self.translator,
+            filters: self.filters.clone(),
+            with: self.with.clone(),
+            style: self.style,
+            template: self.template.clone(),
+            alarm: self.alarm.clone(),
+            exam_alarm: self.exam_alarm.clone(),
+            exams: self.exams,
+            stop_at_session: self.exams && self.stop_at_session,
+            ..Default::default()
+        }
+    }
+}
+
+impl Subscription {
+    pub fn request(&self) -> Request {
+        Request {
+            overrides: self.overrides.clone(),
+            ..self.params.request()
+        }
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+fn random_string(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+/// Only hashes of secrets are stored.
+fn hash(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+fn to_json_error(e: serde_json::Error) -> rusqlite::Error {
+    rusqlite::Error::ToSqlConversionFailure(Box::new(e))
+}
+
+fn from_json_error(column: usize, e: serde_json::Error) -> rusqlite::Error {
+    rusqlite::Error::FromSqlConversionFailure(column, rusqlite::types::Type::Text, Box::new(e))
+}
+
+/// Stores a subscription under a new random token. Returns it along
+/// with the secret needed to manage it, which is not kept anywhere.
+pub fn create(
+    conn: &Connection,
+    new: NewSubscription,
+    now: i64,
+) -> rusqlite::Result<(Subscription, String)> {
+    let params_json = serde_json::to_string(&new.params).map_err(to_json_error)?;
+    let overrides_json = serde_json::to_string(&new.overrides).map_err(to_json_error)?;
+    let expires_at = new
+        .expires_in_days
+        .map(|days| now + i64::from(days) * 24 * 3600);
+    let secret = random_string(SECRET_LEN);
+
+    loop {
+        let token = random_string(TOKEN_LEN);
+        let inserted = conn.execute(
+            "INSERT INTO subscriptions
+             (token, secret_hash, params, created_at, expires_at, overrides)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT (token) DO NOTHING",
+            params![
+                token,
+                hash(&secret),
+                params_json,
+                now,
+                expires_at,
+                overrides_json
+            ],
+        )?;
+        if inserted == 1 {
+            let subscription = Subscription {
+                token,
+                params: new.params,
+                created_at: now,
+                expires_at,
+                hits: 0,
+                last_used_at: None,
+                overrides: new.overrides,
+            };
+            return Ok((subscription, secret));
+        }
+    }
+}
+
+/// Whether the subscription exists and `secret` is the one it was
+/// created with.
+pub fn is_managed_by(conn: &Connection, token: &str, secret: &str) -> rusqlite::Result<bool> {
+    let stored: Option<String> = conn
+        .query_row(
+            "SELECT secret_hash FROM subscriptions WHERE token = ?1",
+            [token],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(stored.is_some_and(|stored| stored == hash(secret)))
+}
+
+pub fn get(conn: &Connection, token: &str) -> rusqlite::Result<Option<Subscription>> {
+    conn.query_row(
+        "SELECT params, created_at, expires_at, hits, last_used_at, overrides
+         FROM subscriptions WHERE token = ?1",
+        [token],
+        |row| {
+            let params: String = row.get(0)?;
+            let params = serde_json::from_str(&params).map_err(|e| from_json_error(0, e))?;
+            let overrides: String = row.get(5)?;
+            let overrides = serde_json::from_str(&overrides).map_err(|e| from_json_error(5, e))?;
+            Ok(Subscription {
+                token: token.to_string(),
+                params,
+                created_at: row.get(1)?,
+                expires_at: row.get(2)?,
+                hits: row.get(3)?,
+                last_used_at: row.get(4)?,
+                overrides,
+            })
+        },
+    )
+    .optional()
+}
+
+/// Looks up a subscription for serving it, counting the hit.
+pub fn follow(conn: &Connection, token: &str, now: i64) -> rusqlite::Result<Lookup> {
+    let Some(mut subscription) = get(conn, token)? else {
+        return Ok(Lookup::Missing);
+    };
+    if subscription.is_expired(now) {
+        return Ok(Lookup::Expired);
+    }
+
+    conn.execute(
+        "UPDATE subscriptions SET hits = hits + 1, last_used_at = ?2 WHERE token = ?1",
+        params![token, now],
+    )?;
+    subscription.hits += 1;
+    subscription.last_used_at = Some(now);
+    Ok(Lookup::Found(Box::new(subscription)))
+}
+
+/// Replaces personal overrides. Returns whether the subscription exists.
+pub fn set_overrides(
+    conn: &Connection,
+    token: &str,
+    overrides: &Overrides,
+) -> rusqlite::Result<bool> {
+    let overrides = serde_json::to_string(overrides).map_err(to_json_error)?;
+    Ok(conn.execute(
+        "UPDATE subscriptions SET overrides = ?2 WHERE token = ?1",
+        params![token, overrides],
+    )? == 1)
+}
+
+/// Returns whether the subscription existed.
+pub fn remove(conn: &Connection, token: &str) -> rusqlite::Result<bool> {
+    Ok(conn.execute("DELETE FROM subscriptions WHERE token = ?1", [token])? == 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    #[test]
+    fn tokens_count_hits_and_expire() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        db::migrate(&mut conn).unwrap();
+
+        let new: NewSubscription = serde_json::from_str(
+            r#"{
+                "department": "knt",
+                "form": "full",
+                "group": "351",
+                "subgroups": ["2_под.", "цифровая_кафедра"],
+                "translator": true,
+                "alarm": ["15m"],
+                "expires_in_days": 1
+            }"#,
+        )
+        .unwrap();
+        let (created, secret) = create(&conn, new, 1000).unwrap();
+        assert_eq!(created.token.len(), TOKEN_LEN);
+        assert_eq!(secret.len(), SECRET_LEN);
+        assert!(is_managed_by(&conn, &created.token, &secret).unwrap());
+        assert!(!is_managed_by(&conn, &created.token, &created.token).unwrap());
+        assert!(!is_managed_by(&conn, "missing", &secret).unwrap());
+        assert_eq!(created.expires_at, Some(1000 + 24 * 3600));
+
+        let Lookup::Found(found) = follow(&conn, &created.token, 2000).unwrap() else {
+            panic!("subscription should be found");
+        };
+        assert_eq!(found.hits, 1);
+        assert_eq!(found.params, created.params);
+        let req = found.params.request();
+        assert_eq!(req.subgroups, ["2_под.", "цифровая_кафедра"]);
+        assert!(req.translator);
+
+        follow(&conn, &created.token, 3000).unwrap();
+        let stored = get(&conn, &created.token).unwrap().unwrap();
+        assert_eq!((stored.hits, stored.last_used_at), (2, Some(3000)));
+        assert!(stored.overrides.is_empty());
+
+        let mut overrides = Overrides::default();
+        overrides.lessons.insert(
+            7,
+            crate::overrides::LessonOverride {
+                hide: true,
+                ..Default::default()
+            },
+        );
+        assert!(set_overrides(&conn, &created.token, &overrides).unwrap());
+        let stored = get(&conn, &created.token).unwrap().unwrap();
+        assert_eq!(stored.request().overrides, overrides);
+        assert!(!set_overrides(&conn, "missing", &overrides).unwrap());
+
+        assert_eq!(
+            follow(&conn, &created.token, 1000 + 24 * 3600).unwrap(),
+            Lookup::Expired
+        );
+        assert!(remove(&conn, &created.token).unwrap());
+        assert_eq!(
+            follow(&conn, &created.token, 3000).unwrap(),
+            Lookup::Missing
+        );
+    }
+}
@@ -0,0 +1,243 @@
+use crate::{calendar::SummaryStyle, config::Config, models::*};
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Text of an event with `{placeholder}` substitution. Use `{{` and `}}`
+/// for literal braces. Unknown placeholders are left as is.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EventTemplate {
+    pub summary: String,
+    pub description: String,
+    pub location: String,
+}
+
+/// Named set of templates selected with `template` request parameter.
+/// Missing templates fall back to the defaults.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TemplatePreset {
+    pub lesson: Option<EventTemplate>,
+    pub exam: Option<EventTemplate>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Templates {
+    pub lesson: EventTemplate,
+    pub exam: EventTemplate,
+    pub presets: HashMap<String, TemplatePreset>,
+}
+
+pub struct RenderedEvent {
+    pub summary: String,
+    pub description: String,
+    pub location: String,
+}
+
+impl Default for EventTemplate {
+    fn default() -> Self {
+        Self {
+            summary: String::from("{summary}"),
+            description: String::from("{teacher}"),
+            location: String::from("{place}"),
+        }
+    }
+}
+
+impl Default for Templates {
+    fn default() -> Self {
+        let initials = TemplatePreset {
+            lesson: Some(EventTemplate {
+                summary: String::from("{name} ({type_short}) {teacher_short}"),
+                ..Default::default()
+            }),
+            exam: None,
+        };
+        let detailed = TemplatePreset {
+            lesson: Some(EventTemplate {
+                description: String::from(
+                    "{type_full}, {lesson_number} пара\n{teacher}\n{sub_group}",
+                ),
+                location: String::from("{address}"),
+                ..Default::default()
+            }),
+            exam: Some(EventTemplate {
+                location: String::from("{address}"),
+                ..Default::default()
+            }),
+        };
+
+        Self {
+            lesson: EventTemplate::default(),
+            exam: EventTemplate::default(),
+            presets: HashMap::from([
+                (String::from("initials"), initials),
+                (String::from("detailed"), detailed),
+            ]),
+        }
+    }
+}
+
+impl Templates {
+    pub fn lesson(&self, preset: Option<&str>) -> &EventTemplate {
+        preset
+            .and_then(|name| self.presets.get(name))
+            .and_then(|preset| preset.lesson.as_ref())
+            .unwrap_or(&self.lesson)
+    }
+
+    pub fn exam(&self, preset: Option<&str>) -> &EventTemplate {
+        preset
+            .and_then(|name| self.presets.get(name))
+            .and_then(|preset| preset.exam.as_ref())
+            .unwrap_or(&self.exam)
+    }
+}
+
+impl EventTemplate {
+    pub fn render(&self, vars: &HashMap<&str, String>) -> RenderedEvent {
+        RenderedEvent {
+            summary: render(&self.summary, vars),
+            description: render(&self.description, vars),
+            location: render(&self.location, vars),
+        }
+    }
+}
+
+/// Substitutes `{name}` placeholders with values from `vars`.
+pub fn render(template: &str, vars: &HashMap<&str, String>) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(pos) = rest.find(['{', '}']) {
+        result.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            result.push_str(&rest[..1]);
+            rest = &rest[2..];
+            continue;
+        }
+
+        let key = rest[1..].find('}').map(|end| &rest[1..end + 1]);
+        match key.and_then(|key| vars.get(key).map(|value| (key, value))) {
+            Some((key, value)) => {
+                result.push_str(value);
+                rest = &rest[key.len() + 2..];
+            }
+            None => {
+                result.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+
+    result
+}
+
+/// Address of the longest `places` key the place starts with.
+fn address(cfg: &Config, place: &str) -> String {
+    cfg.places
+        .iter()
+        .filter(|(building, _)| place.starts_with(building.as_str()))
+        .max_by_key(|(building, _)| building.len())
+        .map(|(_, address)| address.clone())
+        .unwrap_or_else(|| place.to_string())
+}
+
+impl Teacher {
+    /// Surname with initials, e.g. "Иванов И. И."
+    pub fn short(&self) -> String {
+        let initials = [&self.name, &self.patronymic]
+            .into_iter()
+            .filter_map(|part| part.chars().next())
+            .map(|c| format!(" {c}."))
+            .collect::<String>();
+        format!("{}{}", self.surname, initials)
+    }
+}
+
+impl Lesson {
+    pub fn template_vars(
+        &self,
+        cfg: &Config,
+        style: SummaryStyle,
+    ) -> HashMap<&'static str, String> {
+        let label = cfg.lesson_type_label(&self.lesson_type);
+        let time = &self.lesson_time;
+
+        HashMap::from([
+            ("summary", self.summary(cfg, style)),
+            ("id", self.id.to_string()),
+            ("name", self.name.clone()),
+            ("place", self.place.clone()),
+            ("address", address(cfg, &self.place)),
+            ("department", self.department.short_name.clone()),
+            ("group", self.student_group.group_number_rus.clone()),
+            ("sub_group", self.sub_group.trim().to_string()),
+            ("day_number", self.day.day_number.to_string()),
+            ("lesson_number", time.lesson_number.to_string()),
+            (
+                "start",
+                format!("{:02}:{:02}", time.hour_start, time.minute_start),
+            ),
+            (
+                "end",
+                format!("{:02}:{:02}", time.hour_end, time.minute_end),
+            ),
+            ("teacher", self.teacher.full()),
+            ("teacher_short", self.teacher.short()),
+            ("week_type", self.week_type.to_string()),
+            ("lesson_type", self.lesson_type.to_string()),
+            ("type_short", label.short),
+            ("type_full", label.full),
+        ])
+    }
+}
+
+impl ExamEvent {
+    pub fn template_vars(&self, cfg: &Config) -> HashMap<&'static str, String> {
+        let category = cfg
+            .exams
+            .styles
+            .get(&self.exam_period_event_type)
+            .map(|style| style.category.clone())
+            .unwrap_or_else(|| self.exam_period_event_type.to_string());
+
+        HashMap::from([
+            ("summary", self.summary()),
+            ("id", self.id.to_string()),
+            ("name", self.subject_name.clone()),
+            ("place", self.place.clone()),
+            ("address", address(cfg, &self.place)),
+            (
+                "department",
+                self.student_group.department.short_name.clone(),
+            ),
+            ("group", self.student_group.group_number_rus.clone()),
+            ("date", format!("{:02}.{:02}", self.day, self.month.number)),
+            ("start", format!("{:02}:{:02}", self.hour, self.minute)),
+            ("teacher", self.teacher.full()),
+            ("teacher_short", self.teacher.short()),
+            ("exam_type", self.exam_period_event_type.to_string()),
+            ("category", category),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_placeholders() {
+        let vars = HashMap::from([("name", String::from("Алгебра")), ("n", String::from("2"))]);
+
+        assert_eq!(render("{name} ({n})", &vars), "Алгебра (2)");
+        assert_eq!(render("{{name}} {unknown}", &vars), "{name} {unknown}");
+        assert_eq!(render("{name", &vars), "{name");
+        assert_eq!(render("}{n}{", &vars), "}2{");
+    }
+}
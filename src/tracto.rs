@@ -1,18 +1,27 @@
-use crate::{models::*, Config, Request};
+use crate::{alarm::validate_reminders, models::*, subgroup, Config, Request};
 
-#[derive(Debug)]
-pub struct RequestError(String);
-type RequestResult<T> = Result<T, RequestError>;
-
-impl std::fmt::Display for RequestError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}", self.0)
-    }
+#[derive(Debug, thiserror::Error)]
+pub enum RequestError {
+    /// Parameters of the request are wrong
+    #[error("{0}")]
+    Invalid(String),
+    /// Tracto cannot be reached or sent something unexpected
+    #[error("{0}")]
+    Upstream(String),
+    #[error("{0}")]
+    Io(String),
 }
+pub type RequestResult<T> = Result<T, RequestError>;
 
 impl From<reqwest::Error> for RequestError {
     fn from(e: reqwest::Error) -> RequestError {
-        Self(e.to_string())
+        Self::Upstream(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for RequestError {
+    fn from(e: std::io::Error) -> RequestError {
+        Self::Io(e.to_string())
     }
 }
 
@@ -28,7 +37,7 @@ where
     }
 
     let body = response?.json::<T>().await;
-    if let Err(_) = &body {
+    if body.is_err() {
         log::error!(
             "Cannot deserialize response from {url} into {}",
             std::any::type_name::<T>()
@@ -53,7 +62,13 @@ pub async fn fetch_departments(cfg: &Config) -> RequestResult<DepartmentsList> {
     make_request::<DepartmentsList>(url).await
 }
 
-pub async fn fetch_exam(cfg: &Config, request: &Request) -> RequestResult<ExamList>{
+pub async fn fetch_groups(cfg: &Config, department: &str, form: &str) -> RequestResult<GroupsList> {
+    let url = format!("{}/groups/{}/{}", cfg.tracto_prefix, form, department);
+
+    make_request::<GroupsList>(url).await
+}
+
+pub async fn fetch_exam(cfg: &Config, request: &Request) -> RequestResult<ExamList> {
     let url = format!(
         "{}/exam/{}/{}/{}",
         cfg.tracto_prefix, request.form, request.department, request.group
@@ -74,7 +89,82 @@ pub fn find_subgroups(schedule: &Schedule) -> Vec<String> {
     subgroups
 }
 
-pub async fn validate_request(cfg: &Config, req: &Request) -> RequestResult<()> {
+/// Spells requested subgroups the way the schedule does, or lists
+/// the unknown ones along with the valid ones.
+pub fn resolve_subgroups(
+    requested: &[String],
+    available: &[String],
+) -> Result<Vec<String>, String> {
+    let mut resolved: Vec<String> = Vec::new();
+    let mut unknown = Vec::new();
+    for name in requested {
+        match available
+            .iter()
+            .find(|sg| subgroup::key(sg) == subgroup::key(name))
+        {
+            Some(sg) if !resolved.contains(sg) => resolved.push(sg.clone()),
+            Some(_) => {}
+            None => unknown.push(name.as_str()),
+        }
+    }
+
+    if unknown.is_empty() {
+        return Ok(resolved);
+    }
+    let valid = match available.is_empty() {
+        true => "none".to_string(),
+        false => available.join(", "),
+    };
+    Err(format!(
+        "Unknown subgroup(s): {}. Valid subgroups: {valid}",
+        unknown.join(", ")
+    ))
+}
+
+/// Request checked against the schedules of its groups.
+pub struct Validated {
+    /// Schedule of the main group, fetched for the check
+    pub schedule: Schedule,
+    /// Schedules of the groups in `with`, in the same order
+    pub with: Vec<Schedule>,
+    /// Warnings about picking conflicting subgroups, the request is valid anyway
+    pub warnings: Vec<String>,
+}
+
+/// Checks the request and every group merged into it, fixing
+/// the spelling of subgroups.
+pub async fn validate_request(cfg: &Config, req: &mut Request) -> RequestResult<Validated> {
+    let (schedule, mut warnings) = validate_group(cfg, req).await?;
+    let mut with = Vec::new();
+    for i in 0..req.with.len() {
+        let mut member = req.with[i].request(req);
+        let (member_schedule, member_warnings) = validate_group(cfg, &mut member).await?;
+        with.push(member_schedule);
+        warnings.extend(member_warnings);
+        req.with[i].subgroups = member.subgroups;
+    }
+    Ok(Validated {
+        schedule,
+        with,
+        warnings,
+    })
+}
+
+async fn validate_group(cfg: &Config, req: &mut Request) -> RequestResult<(Schedule, Vec<String>)> {
+    if let Err(e) = validate_reminders(cfg, &req.alarm, &req.exam_alarm) {
+        log::error!("Incorrect reminders: {e}.");
+        return Err(RequestError::Invalid(e));
+    }
+
+    if let Some(template) = &req.template {
+        if !cfg.templates.presets.contains_key(template) {
+            log::error!("Unknown template: {template}.");
+            return Err(RequestError::Invalid(format!(
+                "Unknown template: {template}"
+            )));
+        }
+    }
+
     let available_departments: Vec<String> = fetch_departments(cfg)
         .await?
         .departments_list
@@ -83,25 +173,32 @@ pub async fn validate_request(cfg: &Config, req: &Request) -> RequestResult<()>
         .collect();
 
     if !available_departments.contains(&req.department) {
-        log::error!("Incorrect department: {}.",&req.department);
-        return Err(RequestError("Incorrect department".into()));
+        log::error!("Incorrect department: {}.", &req.department);
+        return Err(RequestError::Invalid("Incorrect department".into()));
     }
 
-    if !vec!["full", "extramural"].contains(&req.form.as_str()) {
-        log::error!("Incorrect education form: {}.",&req.form.as_str());
-        return Err(RequestError(
+    if EducationForm::from_known(&req.form).is_none() {
+        log::error!("Incorrect education form: {}.", &req.form.as_str());
+        return Err(RequestError::Invalid(
             "Incorrect education form. Should be \"full\" or \"extramural\"".into(),
         ));
     }
 
     let schedule = fetch_schedule(cfg, req).await?;
-    let subgroups = find_subgroups(&schedule);
-    if req.subgroups.iter().any(|x| !subgroups.contains(x)) {
-        log::error!("Incorrect subgroup(s).");
-        return Err(RequestError("Incorrect subgroup(s)".into()));
+    match resolve_subgroups(&req.subgroups, &find_subgroups(&schedule)) {
+        Ok(subgroups) => req.subgroups = subgroups,
+        Err(e) => {
+            log::error!("{e}.");
+            return Err(RequestError::Invalid(e));
+        }
+    }
+    let categories = subgroup::categories(&cfg.subgroups, &schedule);
+    let warnings = subgroup::conflicts(&categories, &req.subgroups);
+    for warning in &warnings {
+        log::warn!("{warning}.");
     }
 
-    Ok(())
+    Ok((schedule, warnings))
 }
 
 #[cfg(test)]
@@ -123,7 +220,7 @@ mod tests {
             form: String::from("full"),
             group: String::from("351"),
             subgroups: vec![String::from("1_под."), String::from("цифровая_кафедра")],
-            translator: false,
+            ..Default::default()
         };
         fetch_schedule(&cfg, &request).await?;
         Ok(())
@@ -136,8 +233,7 @@ mod tests {
             department: String::from("knt"),
             form: String::from("full"),
             group: String::from("351"),
-            subgroups: Vec::new(),
-            translator: false,
+            ..Default::default()
         };
         fetch_schedule(&cfg, &request).await?;
         Ok(())
@@ -156,8 +252,33 @@ mod tests {
                 String::from("анг.ст.3"),
             ],
             translator: true,
+            ..Default::default()
         };
         fetch_schedule(&cfg, &request).await?;
         Ok(())
     }
+
+    #[test]
+    fn subgroups_resolve_loosely() {
+        let available = vec![
+            String::from("1_под."),
+            String::from("анг.ст.3"),
+            String::from("цифровая_кафедра"),
+        ];
+        let requested = vec![
+            String::from(" Цифровая_Кафедра "),
+            String::from("АНГ. ст.3"),
+            String::from("цифровая_кафедра"),
+        ];
+        assert_eq!(
+            resolve_subgroups(&requested, &available).unwrap(),
+            ["цифровая_кафедра", "анг.ст.3"]
+        );
+
+        let requested = vec![String::from("1_под."), String::from("3_под.")];
+        assert_eq!(
+            resolve_subgroups(&requested, &available).unwrap_err(),
+            "Unknown subgroup(s): 3_под.. Valid subgroups: 1_под., анг.ст.3, цифровая_кафедра"
+        );
+    }
 }
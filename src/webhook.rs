@@ -0,0 +1,747 @@
+use crate::{
+    db::{self, Stored},
+    diff::{ExamDiff, ScheduleDiff},
+    models::{ExamList, Schedule},
+    snapshot, tracto, Config, Request,
+};
+
+use chrono::{TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use rand::{distributions::Alphanumeric, Rng};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs},
+    time::Duration,
+};
+
+/// Header with hex encoded HMAC-SHA256 of the request body.
+pub const SIGNATURE_HEADER: &str = "X-Calar-Signature";
+
+/// Number of the latest deliveries shown for a webhook.
+pub const DELIVERIES_SHOWN: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebhooksConfig {
+    /// How often schedules of groups with webhooks are refreshed,
+    /// at least every minute
+    pub refresh_minutes: u64,
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for every next one
+    pub retry_delay_secs: u64,
+    pub timeout_secs: u64,
+    /// Expected as `Authorization: Bearer <token>` to register a webhook
+    /// for any host
+    pub admin_token: Option<String>,
+    /// Hosts webhooks can be registered for without the admin token
+    pub allowed_hosts: Vec<String>,
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        Self {
+            refresh_minutes: 60,
+            max_attempts: 3,
+            retry_delay_secs: 10,
+            timeout_secs: 10,
+            admin_token: None,
+            allowed_hosts: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    pub id: String,
+    pub url: String,
+    pub secret: String,
+    pub department: String,
+    pub form: String,
+    pub group: String,
+    /// Only changes of these subgroups (and of the whole group) are sent
+    #[serde(default)]
+    pub subgroups: Vec<String>,
+    /// Timestamps of the latest snapshots already delivered
+    pub schedule_seen: Option<i64>,
+    pub exams_seen: Option<i64>,
+}
+
+/// Webhook as shown to anyone knowing its id, without the secret.
+#[derive(Debug, Serialize)]
+pub struct WebhookInfo {
+    pub id: String,
+    pub url: String,
+    pub department: String,
+    pub form: String,
+    pub group: String,
+    pub subgroups: Vec<String>,
+    pub schedule_seen: Option<i64>,
+    pub exams_seen: Option<i64>,
+}
+
+impl From<Webhook> for WebhookInfo {
+    fn from(webhook: Webhook) -> Self {
+        Self {
+            id: webhook.id,
+            url: webhook.url,
+            department: webhook.department,
+            form: webhook.form,
+            group: webhook.group,
+            subgroups: webhook.subgroups,
+            schedule_seen: webhook.schedule_seen,
+            exams_seen: webhook.exams_seen,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewWebhook {
+    pub url: String,
+    pub department: String,
+    pub form: String,
+    pub group: String,
+    #[serde(default)]
+    pub subgroups: Vec<String>,
+    /// Generated if missing
+    pub secret: Option<String>,
+}
+
+/// One attempt to deliver a payload.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Delivery {
+    pub webhook_id: String,
+    pub timestamp: String,
+    pub attempt: u32,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Payload<'a> {
+    pub event: &'static str,
+    pub webhook_id: &'a str,
+    pub department: &'a str,
+    pub form: &'a str,
+    pub group: &'a str,
+    pub subgroups: &'a [String],
+    pub schedule: ScheduleDiff,
+    pub exams: ExamDiff,
+}
+
+impl WebhooksConfig {
+    /// Whether a webhook for `url` can be registered by someone
+    /// presenting `token`.
+    pub fn may_register(&self, url: &str, token: Option<&str>) -> bool {
+        let is_admin = self
+            .admin_token
+            .as_ref()
+            .zip(token)
+            .is_some_and(|(admin, token)| {
+                Sha256::digest(admin.as_bytes()) == Sha256::digest(token.as_bytes())
+            });
+        let host = reqwest::Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_lowercase));
+        is_admin || host.is_some_and(|host| self.allowed_hosts.contains(&host))
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        // "This network", shared address space, IETF protocol assignments,
+        // benchmarking and reserved ranges
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))
+        || (a == 192 && b == 0 && ip.octets()[2] == 0)
+        || (a == 198 && (18..20).contains(&b))
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    if let Some(ip) = ip.to_ipv4_mapped() {
+        return is_public_v4(ip);
+    }
+    let first = ip.segments()[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // Unique local, link-local and documentation ranges
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80
+        || (first == 0x2001 && ip.segments()[1] == 0x0db8))
+}
+
+/// Whether the address is reachable from the internet. Loopback,
+/// private, link-local (cloud metadata services among them) and other
+/// special addresses are not.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+/// Resolves the host of a webhook URL. Fails unless it is http(s)
+/// and every address of the host is public. Blocks on DNS.
+pub fn resolve_public(url: &str) -> Result<(String, Vec<SocketAddr>), String> {
+    let url = reqwest::Url::parse(url).map_err(|e| format!("Incorrect url: {e}"))?;
+    if !["http", "https"].contains(&url.scheme()) {
+        return Err("Webhook url should be http(s)".into());
+    }
+    let host = url.host_str().ok_or("Webhook url has no host")?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    // IPv6 hosts come in brackets
+    let bare = host.trim_start_matches('[').trim_end_matches(']');
+
+    let addrs: Vec<SocketAddr> = (bare, port)
+        .to_socket_addrs()
+        .map_err(|e| format!("Cannot resolve {host}: {e}"))?
+        .collect();
+    if addrs.is_empty() {
+        return Err(format!("Cannot resolve {host}"));
+    }
+    if let Some(addr) = addrs.iter().find(|addr| !is_public(addr.ip())) {
+        return Err(format!(
+            "{host} resolves to non-public address {}",
+            addr.ip()
+        ));
+    }
+
+    Ok((bare.to_string(), addrs))
+}
+
+impl Webhook {
+    pub fn request(&self) -> Request {
+        Request {
+            department: self.department.clone(),
+            form: self.form.clone(),
+            group: self.group.clone(),
+            subgroups: self.subgroups.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+fn random_string(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+fn to_json_error(e: serde_json::Error) -> rusqlite::Error {
+    rusqlite::Error::ToSqlConversionFailure(Box::new(e))
+}
+
+fn db_error(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+const COLUMNS: &str =
+    "id, url, secret, department, form, group_name, subgroups, schedule_seen, exams_seen";
+
+fn from_row(row: &Row) -> rusqlite::Result<Webhook> {
+    let subgroups: String = row.get(6)?;
+    Ok(Webhook {
+        id: row.get(0)?,
+        url: row.get(1)?,
+        secret: row.get(2)?,
+        department: row.get(3)?,
+        form: row.get(4)?,
+        group: row.get(5)?,
+        subgroups: serde_json::from_str(&subgroups).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(6, rusqlite::types::Type::Text, Box::new(e))
+        })?,
+        schedule_seen: row.get(7)?,
+        exams_seen: row.get(8)?,
+    })
+}
+
+fn latest_snapshot<T: Stored>(req: &Request) -> io::Result<Option<i64>> {
+    Ok(snapshot::load_latest::<T>(req)?.map(|snapshot| snapshot.taken_at.timestamp()))
+}
+
+/// Adds a webhook. Changes are reported relative to the snapshots
+/// existing at the moment of registration.
+pub fn register(conn: &Connection, new: NewWebhook) -> io::Result<Webhook> {
+    let mut webhook = Webhook {
+        id: random_string(16),
+        url: new.url,
+        secret: new.secret.unwrap_or_else(|| random_string(32)),
+        department: new.department,
+        form: new.form,
+        group: new.group,
+        subgroups: new.subgroups,
+        schedule_seen: None,
+        exams_seen: None,
+    };
+    let req = webhook.request();
+    webhook.schedule_seen = latest_snapshot::<Schedule>(&req)?;
+    webhook.exams_seen = latest_snapshot::<ExamList>(&req)?;
+    insert(conn, &webhook).map_err(db_error)?;
+
+    Ok(webhook)
+}
+
+fn insert(conn: &Connection, webhook: &Webhook) -> rusqlite::Result<()> {
+    let subgroups = serde_json::to_string(&webhook.subgroups).map_err(to_json_error)?;
+    conn.execute(
+        &format!("INSERT INTO webhooks ({COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)"),
+        params![
+            webhook.id,
+            webhook.url,
+            webhook.secret,
+            webhook.department,
+            webhook.form,
+            webhook.group,
+            subgroups,
+            webhook.schedule_seen,
+            webhook.exams_seen
+        ],
+    )?;
+    Ok(())
+}
+
+pub fn get(conn: &Connection, id: &str) -> rusqlite::Result<Option<Webhook>> {
+    conn.query_row(
+        &format!("SELECT {COLUMNS} FROM webhooks WHERE id = ?1"),
+        [id],
+        from_row,
+    )
+    .optional()
+}
+
+fn all(conn: &Connection) -> rusqlite::Result<Vec<Webhook>> {
+    let mut stmt = conn.prepare(&format!("SELECT {COLUMNS} FROM webhooks ORDER BY id"))?;
+    let rows = stmt.query_map([], from_row)?;
+    rows.collect()
+}
+
+/// Returns whether the webhook existed.
+pub fn remove(conn: &Connection, id: &str) -> rusqlite::Result<bool> {
+    Ok(conn.execute("DELETE FROM webhooks WHERE id = ?1", [id])? == 1)
+}
+
+/// Latest `limit` delivery attempts of a webhook, newest first.
+pub fn deliveries(conn: &Connection, id: &str, limit: usize) -> rusqlite::Result<Vec<Delivery>> {
+    let mut stmt = conn.prepare(
+        "SELECT webhook_id, timestamp, attempt, status, error FROM webhook_deliveries
+         WHERE webhook_id = ?1
+         ORDER BY id DESC LIMIT ?2",
+    )?;
+    let rows = stmt.query_map(params![id, limit as i64], |row| {
+        Ok(Delivery {
+            webhook_id: row.get(0)?,
+            timestamp: row.get(1)?,
+            attempt: row.get(2)?,
+            status: row.get(3)?,
+            error: row.get(4)?,
+        })
+    })?;
+    rows.collect()
+}
+
+fn log_deliveries(conn: &Connection, deliveries: &[Delivery]) -> rusqlite::Result<()> {
+    for delivery in deliveries {
+        conn.execute(
+            "INSERT INTO webhook_deliveries (webhook_id, timestamp, attempt, status, error)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                delivery.webhook_id,
+                delivery.timestamp,
+                delivery.attempt,
+                delivery.status,
+                delivery.error
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+/// Marks changes up to given snapshots as delivered.
+fn set_seen(conn: &Connection, webhook: &Webhook) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE webhooks SET schedule_seen = ?2, exams_seen = ?3 WHERE id = ?1",
+        params![webhook.id, webhook.schedule_seen, webhook.exams_seen],
+    )?;
+    Ok(())
+}
+
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    let signature: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    format!("sha256={signature}")
+}
+
+/// Diff between the snapshot taken at `seen` and the latest one.
+/// Returns the diff and the timestamp of the latest snapshot.
+fn pending<T, D>(
+    req: &Request,
+    seen: Option<i64>,
+    diff: impl Fn(&T, &T) -> D,
+) -> io::Result<Option<(D, i64)>>
+where
+    T: Stored,
+{
+    let Some(latest) = snapshot::load_latest::<T>(req)? else {
+        return Ok(None);
+    };
+    let latest_ts = latest.taken_at.timestamp();
+    let Some(seen) = seen.and_then(|seen| Utc.timestamp_opt(seen, 0).single()) else {
+        // Nothing was seen yet, so the latest snapshot becomes the baseline
+        return Ok(Some((diff(&latest.data, &latest.data), latest_ts)));
+    };
+    if latest.taken_at <= seen {
+        return Ok(None);
+    }
+
+    match snapshot::load_before::<T>(req, seen)? {
+        Some(base) => Ok(Some((diff(&base.data, &latest.data), latest_ts))),
+        None => Ok(None),
+    }
+}
+
+/// POSTs the payload, retrying with exponential backoff.
+/// Every attempt is written to the delivery log.
+pub async fn deliver(cfg: &Config, webhook: &Webhook, body: Vec<u8>) -> bool {
+    // The host is checked again, its addresses may have changed since
+    // registration
+    let url = webhook.url.clone();
+    let resolved = actix_web::rt::task::spawn_blocking(move || resolve_public(&url))
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()));
+    let attempts = match resolved {
+        Ok((host, addrs)) => send(cfg, webhook, body, &host, &addrs).await,
+        Err(error) => {
+            log::warn!("Webhook {} is not delivered: {error}", webhook.id);
+            vec![Delivery {
+                webhook_id: webhook.id.clone(),
+                timestamp: Utc::now().to_rfc3339(),
+                attempt: 1,
+                status: None,
+                error: Some(error),
+            }]
+        }
+    };
+    let logged = db::open().and_then(|conn| log_deliveries(&conn, &attempts));
+    if let Err(e) = logged {
+        log::error!("Cannot write webhook delivery log: {e}");
+    }
+    attempts
+        .last()
+        .is_some_and(|delivery| delivery.error.is_none())
+}
+
+/// Attempts to deliver the payload to `host` at `addrs`, the last one
+/// is successful unless all of them failed. Redirects are not followed,
+/// they could lead anywhere.
+async fn send(
+    cfg: &Config,
+    webhook: &Webhook,
+    body: Vec<u8>,
+    host: &str,
+    addrs: &[SocketAddr],
+) -> Vec<Delivery> {
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .resolve_to_addrs(host, addrs)
+        .build();
+    let client = match client {
+        Ok(client) => client,
+        Err(e) => {
+            return vec![Delivery {
+                webhook_id: webhook.id.clone(),
+                timestamp: Utc::now().to_rfc3339(),
+                attempt: 1,
+                status: None,
+                error: Some(e.to_string()),
+            }]
+        }
+    };
+    let signature = sign(&webhook.secret, &body);
+    let mut delay = Duration::from_secs(cfg.webhooks.retry_delay_secs);
+
+    let mut attempts = Vec::new();
+    for attempt in 1..=cfg.webhooks.max_attempts {
+        let response = client
+            .post(&webhook.url)
+            .timeout(Duration::from_secs(cfg.webhooks.timeout_secs))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, &signature)
+            .body(body.clone())
+            .send()
+            .await;
+
+        let (status, error) = match response {
+            Ok(response) if response.status().is_success() => (Some(response.status()), None),
+            Ok(response) => (Some(response.status()), Some(response.status().to_string())),
+            Err(e) => (e.status(), Some(e.to_string())),
+        };
+        let delivery = Delivery {
+            webhook_id: webhook.id.clone(),
+            timestamp: Utc::now().to_rfc3339(),
+            attempt,
+            status: status.map(|status| status.as_u16()),
+            error,
+        };
+        if delivery.error.is_none() {
+            attempts.push(delivery);
+            break;
+        }
+
+        log::warn!(
+            "Webhook {} delivery attempt {attempt} failed: {}",
+            webhook.id,
+            delivery.error.as_deref().unwrap_or_default()
+        );
+        attempts.push(delivery);
+        if attempt < cfg.webhooks.max_attempts {
+            actix_web::rt::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+
+    attempts
+}
+
+/// Sends pending changes to a webhook and marks them as seen.
+/// Changes stay pending if the delivery fails, so the next refresh
+/// sends them again.
+async fn notify(cfg: &Config, webhook: &mut Webhook) -> io::Result<()> {
+    let req = webhook.request();
+    let schedule = pending::<Schedule, _>(&req, webhook.schedule_seen, ScheduleDiff::new)?;
+    let exams = pending::<ExamList, _>(&req, webhook.exams_seen, ExamDiff::new)?;
+
+    let mut payload = Payload {
+        event: "changes",
+        webhook_id: &webhook.id,
+        department: &webhook.department,
+        form: &webhook.form,
+        group: &webhook.group,
+        subgroups: &webhook.subgroups,
+        schedule: ScheduleDiff::default(),
+        exams: ExamDiff::default(),
+    };
+    let (mut schedule_seen, mut exams_seen) = (webhook.schedule_seen, webhook.exams_seen);
+    if let Some((diff, seen)) = schedule {
+        payload.schedule = diff;
+        schedule_seen = Some(seen);
+    }
+    if let Some((diff, seen)) = exams {
+        payload.exams = diff;
+        exams_seen = Some(seen);
+    }
+    payload.schedule.retain(|lesson| {
+        webhook.subgroups.is_empty()
+            || lesson.sub_group.is_empty()
+            || webhook.subgroups.contains(&lesson.sub_group)
+    });
+
+    if !payload.schedule.is_empty() || !payload.exams.is_empty() {
+        log::info!("Sending changes to webhook {}", webhook.id);
+        let body = serde_json::to_vec(&payload)?;
+        if !deliver(cfg, webhook, body).await {
+            log::error!("Webhook {} is unreachable, keeping changes", webhook.id);
+            return Ok(());
+        }
+    }
+
+    webhook.schedule_seen = schedule_seen;
+    webhook.exams_seen = exams_seen;
+
+    Ok(())
+}
+
+/// Fetches every group with webhooks and notifies about changes.
+pub async fn refresh(cfg: &Config) {
+    let webhooks = match db::open().and_then(|conn| all(&conn)) {
+        Ok(webhooks) => webhooks,
+        Err(e) => {
+            log::error!("Cannot load webhooks: {e}");
+            return;
+        }
+    };
+
+    let mut groups: Vec<Request> = webhooks.iter().map(Webhook::request).collect();
+    groups.sort_by(|a, b| {
+        (&a.department, &a.form, &a.group).cmp(&(&b.department, &b.form, &b.group))
+    });
+    groups
+        .dedup_by(|a, b| (&a.department, &a.form, &a.group) == (&b.department, &b.form, &b.group));
+    for req in &groups {
+        match tracto::fetch_schedule(cfg, req).await {
+            Ok(schedule) => snapshot::record_fetched(req, &schedule).await,
+            Err(e) => log::error!("Cannot refresh schedule of {}: {e}", req.group),
+        }
+        match tracto::fetch_exam(cfg, req).await {
+            Ok(exams) => snapshot::record_fetched(req, &exams).await,
+            Err(e) => log::error!("Cannot refresh exams of {}: {e}", req.group),
+        }
+    }
+
+    for mut webhook in webhooks {
+        if let Err(e) = notify(cfg, &mut webhook).await {
+            log::error!("Cannot notify webhook {}: {e}", webhook.id);
+            continue;
+        }
+        // The webhook may have been removed meanwhile, then nothing is updated
+        if let Err(e) = db::open().and_then(|conn| set_seen(&conn, &webhook)) {
+            log::error!("Cannot save webhook {}: {e}", webhook.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+    };
+
+    /// Accepts `responses.len()` requests, answering with given statuses,
+    /// and returns headers and bodies of received requests.
+    fn receiver(responses: Vec<u16>) -> (String, std::thread::JoinHandle<Vec<(String, String)>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+
+        let handle = std::thread::spawn(move || {
+            let mut received = Vec::new();
+            for status in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut headers = String::new();
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some(len) = line.to_lowercase().strip_prefix("content-length:") {
+                        content_length = len.trim().parse().unwrap();
+                    }
+                    headers.push_str(&line);
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                received.push((headers, String::from_utf8(body).unwrap()));
+
+                write!(stream, "HTTP/1.1 {status} X\r\nContent-Length: 0\r\n\r\n").unwrap();
+            }
+            received
+        });
+
+        (url, handle)
+    }
+
+    #[actix_web::test]
+    async fn delivers_signed_payload_with_retries() {
+        let mut cfg = Config::default();
+        cfg.webhooks.retry_delay_secs = 0;
+        let (url, handle) = receiver(vec![500, 200]);
+        let webhook = Webhook {
+            id: String::from("test"),
+            url,
+            secret: String::from("secret"),
+            department: String::from("knt"),
+            form: String::from("full"),
+            group: String::from("351"),
+            subgroups: Vec::new(),
+            schedule_seen: None,
+            exams_seen: None,
+        };
+
+        let body = br#"{"event":"changes"}"#.to_vec();
+        let addr = webhook.url.parse::<reqwest::Url>().unwrap();
+        let addrs = addr.socket_addrs(|| None).unwrap();
+        let attempts = send(&cfg, &webhook, body.clone(), "127.0.0.1", &addrs).await;
+
+        let received = handle.join().unwrap();
+        assert_eq!(received.len(), 2);
+        let (headers, received_body) = &received[1];
+        assert_eq!(received_body.as_bytes(), body);
+        let signature = sign("secret", &body);
+        assert!(headers
+            .to_lowercase()
+            .contains(&format!("x-calar-signature: {signature}")));
+
+        let mut conn = Connection::open_in_memory().unwrap();
+        db::migrate(&mut conn).unwrap();
+        insert(&conn, &webhook).unwrap();
+        log_deliveries(&conn, &attempts).unwrap();
+        let logged = deliveries(&conn, "test", DELIVERIES_SHOWN).unwrap();
+        assert_eq!(logged.len(), 2);
+        assert_eq!(logged[1].status, Some(500));
+        assert_eq!(logged[0].error, None);
+        assert_eq!(deliveries(&conn, "test", 1).unwrap().len(), 1);
+
+        assert!(remove(&conn, "test").unwrap());
+        assert!(get(&conn, "test").unwrap().is_none());
+        assert!(deliveries(&conn, "test", DELIVERIES_SHOWN)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn only_public_hosts_are_resolved() {
+        for url in [
+            "http://127.0.0.1:8080/hook",
+            "http://localhost/hook",
+            "http://10.1.2.3/hook",
+            "http://192.168.0.1/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://100.64.0.1/hook",
+            "http://[::1]/hook",
+            "http://[fd00:ec2::254]/hook",
+            "http://[::ffff:127.0.0.1]/hook",
+            "ftp://93.184.216.34/hook",
+        ] {
+            assert!(resolve_public(url).is_err(), "{url}");
+        }
+        let (host, addrs) = resolve_public("https://93.184.216.34/hook").unwrap();
+        assert_eq!(host, "93.184.216.34");
+        assert_eq!(addrs, ["93.184.216.34:443".parse().unwrap()]);
+        assert!(is_public("2a00:1450:4010::64".parse().unwrap()));
+    }
+
+    #[test]
+    fn registration_needs_token_or_allowed_host() {
+        let mut cfg = WebhooksConfig::default();
+        assert!(!cfg.may_register("https://bot.example.org/hook", None));
+
+        cfg.admin_token = Some(String::from("admin"));
+        cfg.allowed_hosts = vec![String::from("bot.example.org")];
+        assert!(cfg.may_register("https://bot.example.org/hook", None));
+        assert!(cfg.may_register("https://BOT.example.org/hook", Some("wrong")));
+        assert!(!cfg.may_register("https://other.example.org/hook", Some("wrong")));
+        assert!(cfg.may_register("https://other.example.org/hook", Some("admin")));
+    }
+
+    #[test]
+    fn signature_is_hmac_sha256() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}